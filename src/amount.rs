@@ -0,0 +1,200 @@
+// Parsing of amounts and net debit caps supplied by humans. In addition to plain numbers, we accept
+// thousands separators (10,000), scientific notation (1e6), and the suffixes K, M and B (10K,
+// 2.5M), case-insensitive.
+
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use fspiox_api::{Amount, Currency};
+
+#[derive(Error, Debug)]
+pub enum AmountError {
+    #[error("Couldn't parse {0} as an amount. Examples of valid amounts: 100, 10,000, 2.5M, 1e6")]
+    Invalid(String),
+    #[error("{0} must not be negative")]
+    Negative(String),
+    #[error("{0} must not be zero")]
+    Zero(String),
+    #[error("{0} must be a whole number")]
+    NotWhole(String),
+    #[error("{0} is too large")]
+    TooLarge(String),
+    #[error("{0} has more decimal places than {1} allows. {1} allows {2}.")]
+    TooPrecise(Amount, Currency, u32),
+}
+
+/// Remove thousands separators. Commas are accepted only between groups of three digits in the
+/// whole part of a number, so that for example 10,50 isn't taken to mean 1050.
+fn remove_separators(s: &str) -> Option<String> {
+    let s = s.trim().replace('_', "");
+    if !s.contains(',') {
+        return Some(s);
+    }
+    let unsigned = s.trim_start_matches(&['-', '+'][..]);
+    let end = unsigned.find(|c: char| !c.is_ascii_digit() && c != ',').unwrap_or(unsigned.len());
+    let (whole, rest) = unsigned.split_at(end);
+    if rest.contains(',') {
+        return None;
+    }
+    let mut groups = whole.split(',');
+    let first = groups.next()?;
+    if (1..=3).contains(&first.len()) && groups.all(|g| g.len() == 3) {
+        Some(s.replace(',', ""))
+    } else {
+        None
+    }
+}
+
+fn parse_decimal(s: &str) -> Result<Decimal, AmountError> {
+    let invalid = || AmountError::Invalid(s.to_string());
+    let cleaned = remove_separators(s).ok_or_else(invalid)?;
+    let upper = cleaned.to_uppercase();
+    let (number, multiplier) = match upper.chars().last() {
+        Some('K') => (&cleaned[..cleaned.len() - 1], Decimal::from(1_000)),
+        Some('M') => (&cleaned[..cleaned.len() - 1], Decimal::from(1_000_000)),
+        Some('B') => (&cleaned[..cleaned.len() - 1], Decimal::from(1_000_000_000)),
+        _ => (&cleaned[..], Decimal::ONE),
+    };
+    let value = if number.contains(&['e', 'E'][..]) {
+        Decimal::from_scientific(number)
+    } else {
+        Decimal::from_str(number)
+    }.map_err(|_| invalid())?;
+    value.checked_mul(multiplier).map(|v| v.normalize()).ok_or_else(invalid)
+}
+
+/// Parse an amount, which may be negative
+pub fn parse_amount(s: &str) -> Result<Amount, AmountError> {
+    let value = parse_decimal(s)?;
+    Amount::from_str(&value.to_string()).map_err(|_| AmountError::Invalid(s.to_string()))
+}
+
+/// Parse an amount, which may be negative but not zero
+pub fn parse_nonzero_amount(s: &str) -> Result<Amount, AmountError> {
+    if parse_decimal(s)?.is_zero() {
+        return Err(AmountError::Zero(s.to_string()));
+    }
+    parse_amount(s)
+}
+
+/// Parse a net debit cap, which must be a non-negative whole number
+pub fn parse_ndc(s: &str) -> Result<u32, AmountError> {
+    let value = parse_decimal(s)?;
+    if value.is_sign_negative() && !value.is_zero() {
+        return Err(AmountError::Negative(s.to_string()));
+    }
+    if !value.fract().is_zero() {
+        return Err(AmountError::NotWhole(s.to_string()));
+    }
+    u32::from_str(&value.trunc().to_string()).map_err(|_| AmountError::TooLarge(s.to_string()))
+}
+
+/// The number of decimal places permitted by a currency, per ISO 4217
+pub fn minor_units(currency: Currency) -> u32 {
+    match currency.to_string().as_str() {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" |
+        "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        "CLF" | "UYW" => 4,
+        _ => 2,
+    }
+}
+
+/// Check that an amount has no more decimal places than its currency permits
+pub fn check_precision(amount: Amount, currency: Currency) -> Result<(), AmountError> {
+    let allowed = minor_units(currency);
+    let precise = Decimal::from_str(&amount.to_string())
+        .map(|d| d.normalize().scale() > allowed)
+        .unwrap_or(false);
+    if precise {
+        return Err(AmountError::TooPrecise(amount, currency, allowed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(s: &str) -> Currency {
+        Currency::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn thousands_separators() {
+        assert_eq!(parse_decimal("10,000").unwrap(), Decimal::from(10_000));
+        assert_eq!(parse_decimal("-1,234,567.5").unwrap(), Decimal::from_str("-1234567.5").unwrap());
+        assert_eq!(parse_decimal("10_000").unwrap(), Decimal::from(10_000));
+    }
+
+    #[test]
+    fn misplaced_separators_rejected() {
+        for s in &["10,50", "2,5M", "1,0000", ",100", "1,000.000,5", "1e6,000"] {
+            assert!(matches!(parse_decimal(s), Err(AmountError::Invalid(_))), "{} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn scientific_notation_and_suffixes() {
+        assert_eq!(parse_decimal("1e6").unwrap(), Decimal::from(1_000_000));
+        assert_eq!(parse_decimal("2.5M").unwrap(), Decimal::from(2_500_000));
+        assert_eq!(parse_decimal("10k").unwrap(), Decimal::from(10_000));
+        assert_eq!(parse_decimal("1B").unwrap(), Decimal::from(1_000_000_000));
+    }
+
+    #[test]
+    fn suffix_edge_cases() {
+        assert_eq!(parse_decimal("-2.5M").unwrap(), Decimal::from(-2_500_000));
+        assert_eq!(parse_decimal("-1,000k").unwrap(), Decimal::from(-1_000_000));
+        assert!(parse_decimal("0").unwrap().is_zero());
+        assert!(parse_decimal("0K").unwrap().is_zero());
+        for s in &["K", "m", "-B", "", "-", "1KM", "1.2.3"] {
+            assert!(matches!(parse_decimal(s), Err(AmountError::Invalid(_))), "{:?} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn nonzero_amounts() {
+        assert_eq!(parse_nonzero_amount("-100").unwrap().to_string(), parse_amount("-100").unwrap().to_string());
+        for s in &["0", "-0", "0.00", "0M"] {
+            assert!(matches!(parse_nonzero_amount(s), Err(AmountError::Zero(_))), "{} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn currency_minor_units() {
+        let cases = [("XOF", 0), ("JPY", 0), ("USD", 2), ("EUR", 2), ("KWD", 3), ("TND", 3), ("BHD", 3)];
+        for (code, expected) in cases.iter() {
+            assert_eq!(minor_units(currency(code)), *expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn ndc() {
+        assert_eq!(parse_ndc("10,000").unwrap(), 10_000);
+        assert_eq!(parse_ndc("2.5M").unwrap(), 2_500_000);
+        assert_eq!(parse_ndc("0").unwrap(), 0);
+        assert!(matches!(parse_ndc("-100"), Err(AmountError::Negative(_))));
+        assert!(matches!(parse_ndc("10.5"), Err(AmountError::NotWhole(_))));
+        assert!(matches!(parse_ndc("5B"), Err(AmountError::TooLarge(_))));
+    }
+
+    #[test]
+    fn precision() {
+        let xof = currency("XOF");
+        assert!(check_precision(parse_amount("100").unwrap(), xof).is_ok());
+        assert!(matches!(
+            check_precision(parse_amount("100.5").unwrap(), xof),
+            Err(AmountError::TooPrecise(_, _, 0)),
+        ));
+        assert!(check_precision(parse_amount("100.25").unwrap(), currency("USD")).is_ok());
+        assert!(check_precision(parse_amount("100.500").unwrap(), currency("USD")).is_ok());
+        assert!(check_precision(parse_amount("1.001").unwrap(), currency("KWD")).is_ok());
+        assert!(matches!(
+            check_precision(parse_amount("1.0001").unwrap(), currency("KWD")),
+            Err(AmountError::TooPrecise(_, _, 3)),
+        ));
+    }
+}
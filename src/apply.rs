@@ -0,0 +1,102 @@
+// Assess what's required to converge a switch to the state described by a switch configuration
+// document. Anything present in the switch but absent from the document is left alone; this will
+// only ever create or update.
+
+use fspiox_api::FspId;
+
+use crate::clients::Clients;
+use crate::diff::{self, Difference};
+use crate::operations::{self, Operation};
+use crate::switch_config::SwitchConfig;
+use crate::MojaloopCliError;
+
+/// The operations that resolve a single difference between the configuration and the switch
+pub fn resolve(difference: &Difference) -> Vec<Operation> {
+    match difference {
+        Difference::MissingHubAccount { r#type, currency } =>
+            vec![Operation::CreateHubAccount { r#type: *r#type, currency: *currency }],
+        Difference::MissingSettlementModel { settlement_model } =>
+            vec![Operation::CreateSettlementModel { settlement_model: settlement_model.into() }],
+        // Unresolvable; see check_conflicts
+        Difference::SettlementModelCurrencyMismatch { .. } => Vec::new(),
+        // Participants are created along with their first account
+        Difference::MissingParticipant { .. } => Vec::new(),
+        Difference::MissingParticipantAccount { participant, currency, ndc, position } => vec![
+            Operation::CreateParticipantAccount {
+                participant: participant.clone(),
+                currency: *currency,
+            },
+            Operation::SetInitialPositionAndLimits {
+                participant: participant.clone(),
+                currency: *currency,
+                ndc: *ndc,
+                position: *position,
+            },
+        ],
+        Difference::InactiveParticipantAccount { participant, currency, account_id } =>
+            vec![Operation::SetAccountActive {
+                participant: participant.clone(),
+                currency: *currency,
+                account_id: *account_id,
+                active: true,
+            }],
+        Difference::NetDebitCapMismatch { participant, currency, expected, .. } =>
+            vec![Operation::SetNetDebitCap {
+                participant: participant.clone(),
+                currency: *currency,
+                value: *expected,
+            }],
+        Difference::EndpointMismatch { participant, expected, actual } =>
+            operations::set_endpoints(participant, expected.as_str())
+                .into_iter()
+                .filter(|op| match op {
+                    Operation::SetEndpoint { r#type, .. } => actual.iter().any(|ep| ep.r#type == *r#type),
+                    _ => true,
+                })
+                .collect(),
+    }
+}
+
+/// Fail where the switch conflicts with the configuration in a way we can't resolve
+fn check_conflicts(differences: &[Difference]) -> Result<(), MojaloopCliError> {
+    for difference in differences {
+        if let Difference::SettlementModelCurrencyMismatch { settlement_model, actual } = difference {
+            return Err(MojaloopCliError::SettlementModelConflict(
+                settlement_model.name.to_string(),
+                *actual,
+                settlement_model.currency,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Assess the operations necessary for the switch to match the supplied configuration
+pub async fn plan(config: &SwitchConfig, clients: &mut Clients) -> anyhow::Result<Vec<Operation>> {
+    let differences = diff::diff(config, clients).await?;
+    check_conflicts(&differences)?;
+    Ok(differences.iter().flat_map(resolve).collect())
+}
+
+/// Assess the operations necessary to create anything in the supplied configuration that's missing
+/// from the switch. Unlike `plan`, anything that already exists is left as it is, even where it
+/// differs from the configuration.
+pub async fn plan_missing(config: &SwitchConfig, clients: &mut Clients) -> anyhow::Result<Vec<Operation>> {
+    let differences = diff::diff(config, clients).await?;
+    check_conflicts(&differences)?;
+    let missing_participants: Vec<&FspId> = differences.iter()
+        .filter_map(|d| match d {
+            Difference::MissingParticipant { participant } => Some(participant),
+            _ => None,
+        })
+        .collect();
+    Ok(differences.iter()
+        .filter(|d| match d {
+            Difference::InactiveParticipantAccount { .. } | Difference::NetDebitCapMismatch { .. } => false,
+            // Endpoints are only set on participants we create
+            Difference::EndpointMismatch { participant, .. } => missing_participants.contains(&participant),
+            _ => true,
+        })
+        .flat_map(resolve)
+        .collect())
+}
@@ -0,0 +1,184 @@
+// Lazily-created clients for each of the Mojaloop services. Creating a client opens a port-forward
+// to the service, so we only want to do that for services a given command actually uses.
+//
+// Alternatively, where the services are reachable directly, for example through an ingress or in a
+// docker-compose deployment, clients are created from the url of each service and no Kubernetes
+// API access is required.
+
+use mojaloop_api::clients::{central_ledger, quote, settlement, transfer};
+
+use crate::MojaloopCliError;
+
+/// The base url of each Mojaloop service, for use where the services are reachable without
+/// Kubernetes. Services without a url cannot be used.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceUrls {
+    pub central_ledger: Option<url::Url>,
+    pub settlement: Option<url::Url>,
+    pub quote: Option<url::Url>,
+    pub transfer: Option<url::Url>,
+}
+
+impl ServiceUrls {
+    /// Fill any missing urls from the MOJALOOP_CENTRAL_LEDGER_URL, MOJALOOP_SETTLEMENT_URL,
+    /// MOJALOOP_QUOTE_URL and MOJALOOP_TRANSFER_URL environment variables
+    pub fn or_env(self) -> Result<ServiceUrls, MojaloopCliError> {
+        fn from_env(url: Option<url::Url>, var: &str) -> Result<Option<url::Url>, MojaloopCliError> {
+            match (url, std::env::var(var)) {
+                (Some(url), _) => Ok(Some(url)),
+                (None, Ok(value)) => url::Url::parse(&value)
+                    .map(Some)
+                    .map_err(|e| MojaloopCliError::InvalidServiceUrl(var.to_string(), e.to_string())),
+                (None, Err(_)) => Ok(None),
+            }
+        }
+        Ok(ServiceUrls {
+            central_ledger: from_env(self.central_ledger, "MOJALOOP_CENTRAL_LEDGER_URL")?,
+            settlement: from_env(self.settlement, "MOJALOOP_SETTLEMENT_URL")?,
+            quote: from_env(self.quote, "MOJALOOP_QUOTE_URL")?,
+            transfer: from_env(self.transfer, "MOJALOOP_TRANSFER_URL")?,
+        })
+    }
+
+    /// Fill any missing urls from another set of urls
+    pub fn or(self, other: ServiceUrls) -> ServiceUrls {
+        ServiceUrls {
+            central_ledger: self.central_ledger.or(other.central_ledger),
+            settlement: self.settlement.or(other.settlement),
+            quote: self.quote.or(other.quote),
+            transfer: self.transfer.or(other.transfer),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.central_ledger.is_none() &&
+        self.settlement.is_none() &&
+        self.quote.is_none() &&
+        self.transfer.is_none()
+    }
+}
+
+enum Connection {
+    Kubernetes {
+        k8s: kube::Client,
+        namespace: Option<String>,
+    },
+    Direct(ServiceUrls),
+}
+
+pub struct Clients {
+    connection: Connection,
+    hub: fspiox_api::FspId,
+    central_ledger: Option<central_ledger::Client>,
+    settlement: Option<settlement::Client>,
+    quote: Option<quote::Client>,
+    transfer: Option<transfer::Client>,
+}
+
+/// The url of a service in direct mode, or an error telling the user how to supply it
+fn service_url<'a>(url: &'a Option<url::Url>, service: &str, flag: &str) -> Result<&'a url::Url, MojaloopCliError> {
+    url.as_ref().ok_or_else(|| MojaloopCliError::ServiceUrlNotConfigured(service.to_string(), flag.to_string()))
+}
+
+impl Clients {
+    pub fn new(k8s: kube::Client, namespace: Option<String>) -> Clients {
+        Clients::with_connection(Connection::Kubernetes { k8s, namespace })
+    }
+
+    pub fn direct(urls: ServiceUrls) -> Clients {
+        Clients::with_connection(Connection::Direct(urls))
+    }
+
+    fn with_connection(connection: Connection) -> Clients {
+        Clients {
+            connection,
+            // Older versions of Mojaloop named the hub "hub"; users of those supply --hub-name
+            hub: fspiox_api::FspId::from("Hub").unwrap(),
+            central_ledger: None,
+            settlement: None,
+            quote: None,
+            transfer: None,
+        }
+    }
+
+    /// Use a hub participant name other than "Hub"
+    pub fn with_hub(mut self, hub: fspiox_api::FspId) -> Clients {
+        self.hub = hub;
+        self
+    }
+
+    /// The name of the hub participant
+    pub fn hub(&self) -> fspiox_api::FspId {
+        self.hub.clone()
+    }
+
+    pub fn k8s(&self) -> Result<kube::Client, MojaloopCliError> {
+        match &self.connection {
+            Connection::Kubernetes { k8s, .. } => Ok(k8s.clone()),
+            Connection::Direct(_) => Err(MojaloopCliError::KubernetesRequired),
+        }
+    }
+
+    pub fn namespace(&self) -> &Option<String> {
+        match &self.connection {
+            Connection::Kubernetes { namespace, .. } => namespace,
+            Connection::Direct(_) => &None,
+        }
+    }
+
+    pub async fn central_ledger(&mut self) -> anyhow::Result<&mut central_ledger::Client> {
+        if self.central_ledger.is_none() {
+            self.central_ledger = Some(match &self.connection {
+                Connection::Kubernetes { k8s, namespace } =>
+                    central_ledger::Client::from_k8s_params(Some(k8s.clone()), namespace).await?,
+                Connection::Direct(urls) =>
+                    central_ledger::Client::from_url(
+                        service_url(&urls.central_ledger, "central-ledger", "--central-ledger-url")?
+                    ).await?,
+            });
+        }
+        Ok(self.central_ledger.as_mut().unwrap())
+    }
+
+    pub async fn settlement(&mut self) -> anyhow::Result<&mut settlement::Client> {
+        if self.settlement.is_none() {
+            self.settlement = Some(match &self.connection {
+                Connection::Kubernetes { k8s, namespace } =>
+                    settlement::Client::from_k8s_params(Some(k8s.clone()), namespace).await?,
+                Connection::Direct(urls) =>
+                    settlement::Client::from_url(
+                        service_url(&urls.settlement, "central-settlement", "--settlement-url")?
+                    ).await?,
+            });
+        }
+        Ok(self.settlement.as_mut().unwrap())
+    }
+
+    pub async fn quote(&mut self) -> anyhow::Result<&mut quote::Client> {
+        if self.quote.is_none() {
+            self.quote = Some(match &self.connection {
+                Connection::Kubernetes { k8s, namespace } =>
+                    quote::Client::from_k8s_params(Some(k8s.clone()), namespace).await?,
+                Connection::Direct(urls) =>
+                    quote::Client::from_url(
+                        service_url(&urls.quote, "quoting-service", "--quote-url")?
+                    ).await?,
+            });
+        }
+        Ok(self.quote.as_mut().unwrap())
+    }
+
+    pub async fn transfer(&mut self) -> anyhow::Result<&mut transfer::Client> {
+        if self.transfer.is_none() {
+            self.transfer = Some(match &self.connection {
+                Connection::Kubernetes { k8s, namespace } =>
+                    transfer::Client::from_k8s_params(Some(k8s.clone()), namespace).await?,
+                Connection::Direct(urls) =>
+                    transfer::Client::from_url(
+                        service_url(&urls.transfer, "ml-api-adapter", "--transfer-url")?
+                    ).await?,
+            });
+        }
+        Ok(self.transfer.as_mut().unwrap())
+    }
+}
@@ -0,0 +1,121 @@
+// Parsing of currencies supplied by humans. Currencies are accepted in any case, and where a
+// currency isn't recognised we suggest the closest ISO 4217 codes, because "Matching variant not
+// found" tells the user very little.
+
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use fspiox_api::Currency;
+
+// ISO 4217 alphabetic codes
+const ISO_4217: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT", "BGN",
+    "BHD", "BIF", "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD", "CDF",
+    "CHE", "CHF", "CHW", "CLF", "CLP", "CNY", "COP", "COU", "CRC", "CUC", "CUP", "CVE", "CZK", "DJF",
+    "DKK", "DOP", "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD",
+    "GNF", "GTQ", "GYD", "HKD", "HNL", "HRK", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK",
+    "JMD", "JOD", "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP",
+    "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU", "MUR", "MVR",
+    "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN",
+    "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR", "SDG",
+    "SEK", "SGD", "SHP", "SLL", "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB", "TJS", "TMT",
+    "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "USN", "UYI", "UYU", "UYW", "UZS",
+    "VES", "VND", "VUV", "WST", "XAF", "XAG", "XAU", "XBA", "XBB", "XBC", "XBD", "XCD", "XDR", "XOF",
+    "XPD", "XPF", "XPT", "XSU", "XTS", "XUA", "XXX", "YER", "ZAR", "ZMW", "ZWL",
+];
+
+#[derive(Error, Debug)]
+pub enum CurrencyError {
+    #[error("Unknown currency {0}")]
+    Unknown(String),
+    #[error("Unknown currency {0}. Did you mean {1}?")]
+    UnknownWithSuggestions(String, String),
+}
+
+/// The number of single-character edits required to turn one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            curr.push(substitution.min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+/// The supported currencies closest to the given value, at most three
+fn suggestions(value: &str) -> Vec<&'static str> {
+    let mut candidates: Vec<(usize, &'static str)> = ISO_4217.iter()
+        .filter(|code| Currency::from_str(code).is_ok())
+        .map(|code| (edit_distance(value, code), *code))
+        .filter(|(distance, _)| *distance <= 2)
+        .collect();
+    candidates.sort();
+    let best = candidates.first().map(|(distance, _)| *distance);
+    candidates.into_iter()
+        .take_while(|(distance, _)| Some(*distance) == best)
+        .take(3)
+        .map(|(_, code)| code)
+        .collect()
+}
+
+/// Parse a currency in any case
+pub fn parse_currency(s: &str) -> Result<Currency, CurrencyError> {
+    let value = s.trim().to_uppercase();
+    Currency::from_str(&value).map_err(|_| {
+        let suggestions = suggestions(&value);
+        if suggestions.is_empty() {
+            CurrencyError::Unknown(s.to_string())
+        } else {
+            CurrencyError::UnknownWithSuggestions(s.to_string(), suggestions.join(" or "))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("XOF", "XOF"), 0);
+        assert_eq!(edit_distance("XOG", "XOF"), 1);
+        assert_eq!(edit_distance("SEKK", "SEK"), 1);
+        assert_eq!(edit_distance("", "SEK"), 3);
+        assert_eq!(edit_distance("ABC", "XYZ"), 3);
+    }
+
+    #[test]
+    fn any_case() {
+        for s in &["xof", "XOF", "Xof", " xof "] {
+            assert_eq!(parse_currency(s).unwrap().to_string(), "XOF", "{}", s);
+        }
+    }
+
+    #[test]
+    fn close_matches_suggested() {
+        assert_eq!(suggestions("SEKK"), vec!["SEK"]);
+        match parse_currency("sekk") {
+            Err(CurrencyError::UnknownWithSuggestions(value, suggestions)) => {
+                assert_eq!(value, "sekk");
+                assert_eq!(suggestions, "SEK");
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        // At most three, all at the smallest distance
+        let suggested = suggestions("USX");
+        assert!(!suggested.is_empty() && suggested.len() <= 3);
+        assert!(suggested.iter().all(|c| edit_distance("USX", c) == 1), "{:?}", suggested);
+    }
+
+    #[test]
+    fn unknown_without_suggestions() {
+        assert!(suggestions("QQQQQQ").is_empty());
+        assert!(matches!(parse_currency("QQQQQQ"), Err(CurrencyError::Unknown(value)) if value == "QQQQQQ"));
+    }
+}
@@ -0,0 +1,272 @@
+// Compare a switch configuration document with the live switch. Only things described by the
+// document are compared; anything present in the switch but absent from the document is ignored.
+
+use serde::Serialize;
+use strum::IntoEnumIterator;
+
+use fspiox_api::{Amount, Currency, FspId};
+use mojaloop_api::{
+    central_ledger::participants::{
+        self,
+        AnyAccountType,
+        FspiopCallbackType,
+        GetCallbackUrls,
+        GetDfspAccounts,
+        GetParticipants,
+        HubAccountType,
+        LimitType,
+        SettlementAccountId,
+    },
+    central_ledger::settlement_models,
+    clients::FspiopClient,
+};
+
+use crate::clients::Clients;
+use crate::switch_config::{SettlementModelConfig, SwitchConfig};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EndpointValue {
+    pub r#type: FspiopCallbackType,
+    /// None where the endpoint is not configured
+    pub value: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "difference", rename_all = "snake_case")]
+pub enum Difference {
+    MissingHubAccount {
+        r#type: HubAccountType,
+        currency: Currency,
+    },
+    MissingSettlementModel {
+        settlement_model: SettlementModelConfig,
+    },
+    /// A settlement model of the same name exists in another currency. Settlement model names are
+    /// unique, so this can't be resolved by creating the model.
+    SettlementModelCurrencyMismatch {
+        settlement_model: SettlementModelConfig,
+        actual: Currency,
+    },
+    MissingParticipant {
+        participant: FspId,
+    },
+    MissingParticipantAccount {
+        participant: FspId,
+        currency: Currency,
+        ndc: u32,
+        position: Amount,
+    },
+    InactiveParticipantAccount {
+        participant: FspId,
+        currency: Currency,
+        account_id: SettlementAccountId,
+    },
+    NetDebitCapMismatch {
+        participant: FspId,
+        currency: Currency,
+        expected: u32,
+        /// None where no NDC is set
+        actual: Option<u32>,
+    },
+    EndpointMismatch {
+        participant: FspId,
+        expected: url::Url,
+        /// Only those endpoints that don't match the expected url
+        actual: Vec<EndpointValue>,
+    },
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::MissingHubAccount { r#type, currency } => {
+                let str_hub_acc_type = match r#type {
+                    HubAccountType::HubMultilateralSettlement => "settlement",
+                    HubAccountType::HubReconciliation => "reconciliation",
+                };
+                write!(f, "Hub {} account {} does not exist", str_hub_acc_type, currency)
+            }
+            Difference::MissingSettlementModel { settlement_model } =>
+                write!(f, "Settlement model {} for currency {} does not exist", settlement_model.name, settlement_model.currency),
+            Difference::SettlementModelCurrencyMismatch { settlement_model, actual } =>
+                write!(f, "Settlement model {} is for currency {}, expected {}", settlement_model.name, actual, settlement_model.currency),
+            Difference::MissingParticipant { participant } =>
+                write!(f, "Participant {} does not exist", participant),
+            Difference::MissingParticipantAccount { participant, currency, .. } =>
+                write!(f, "Participant {} has no {} account", participant, currency),
+            Difference::InactiveParticipantAccount { participant, currency, .. } =>
+                write!(f, "Participant {} {} account is inactive", participant, currency),
+            Difference::NetDebitCapMismatch { participant, currency, expected, actual } =>
+                write!(
+                    f,
+                    "Participant {} {} NDC is {}, expected {}",
+                    participant,
+                    currency,
+                    actual.map(|a| a.to_string()).unwrap_or_else(|| "not set".to_string()),
+                    expected,
+                ),
+            Difference::EndpointMismatch { participant, expected, actual } => {
+                write!(f, "Participant {} endpoints do not match {}:", participant, expected)?;
+                for ep in actual {
+                    write!(f, "\n    {:?}: {}", ep.r#type, ep.value.as_deref().unwrap_or("not set"))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn hub_account_ledger_type(t: HubAccountType) -> AnyAccountType {
+    match t {
+        HubAccountType::HubReconciliation => AnyAccountType::HubReconciliation,
+        HubAccountType::HubMultilateralSettlement => AnyAccountType::HubMultilateralSettlement,
+    }
+}
+
+/// The endpoints that were not generated from the given url. Endpoints are supplied as (type,
+/// value) pairs.
+pub fn mismatched_endpoints<'a>(
+    endpoints: impl Iterator<Item = (FspiopCallbackType, &'a str)>,
+    url: &url::Url,
+) -> Vec<EndpointValue> {
+    let base = url.as_str().trim_end_matches('/');
+    let endpoints: Vec<_> = endpoints.collect();
+    FspiopCallbackType::iter()
+        .filter_map(|callback_type| {
+            let value = endpoints.iter().find(|(t, _)| *t == callback_type).map(|(_, v)| *v);
+            match value {
+                Some(v) if v == base || v.starts_with(&format!("{}/", base)) => None,
+                _ => Some(EndpointValue { r#type: callback_type, value: value.map(String::from) }),
+            }
+        })
+        .collect()
+}
+
+/// Find the differences between the supplied configuration and the switch
+pub async fn diff(config: &SwitchConfig, clients: &mut Clients) -> anyhow::Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    let hub = clients.hub();
+    let client = clients.central_ledger().await?;
+
+    let hub_accounts = client.send(GetDfspAccounts { name: hub }).await?.des().await?;
+    for currency in &config.hub.currencies {
+        for hub_account_type in &[HubAccountType::HubReconciliation, HubAccountType::HubMultilateralSettlement] {
+            let exists = hub_accounts.iter().any(|a|
+                a.currency == *currency && a.ledger_account_type == hub_account_ledger_type(*hub_account_type)
+            );
+            if !exists {
+                differences.push(Difference::MissingHubAccount { r#type: *hub_account_type, currency: *currency });
+            }
+        }
+    }
+
+    let settlement_models = client.send(settlement_models::GetSettlementModels {}).await?.des().await?;
+    for model in &config.settlement_models {
+        match settlement_models.iter().find(|m| m.name == model.name) {
+            None => differences.push(Difference::MissingSettlementModel { settlement_model: model.clone() }),
+            Some(m) if m.currency != model.currency => differences.push(Difference::SettlementModelCurrencyMismatch {
+                settlement_model: model.clone(),
+                actual: m.currency,
+            }),
+            Some(_) => {}
+        }
+    }
+
+    let existing_participants = client.send(GetParticipants {}).await?.des().await?;
+    for participant in &config.participants {
+        let existing = existing_participants.iter().find(|p| p.name == participant.name);
+        let existing_limits = match existing {
+            Some(_) => client.send(participants::GetParticipantLimits { name: participant.name.clone() }).await?.des().await?,
+            None => {
+                differences.push(Difference::MissingParticipant { participant: participant.name.clone() });
+                Vec::new()
+            }
+        };
+
+        for account in &participant.accounts {
+            let existing_account = existing.and_then(|p| p.accounts.iter().find(|a|
+                a.currency == account.currency && a.ledger_account_type == AnyAccountType::Position
+            ));
+            match existing_account {
+                None => {
+                    differences.push(Difference::MissingParticipantAccount {
+                        participant: participant.name.clone(),
+                        currency: account.currency,
+                        ndc: account.ndc,
+                        position: account.position,
+                    });
+                }
+                Some(existing_account) => {
+                    if existing_account.is_active != 1 {
+                        differences.push(Difference::InactiveParticipantAccount {
+                            participant: participant.name.clone(),
+                            currency: account.currency,
+                            account_id: existing_account.id,
+                        });
+                    }
+                    let current_ndc = existing_limits.iter()
+                        .find(|l| l.currency == account.currency && l.limit.r#type == LimitType::NetDebitCap)
+                        .map(|l| l.limit.value);
+                    if current_ndc != Some(account.ndc) {
+                        differences.push(Difference::NetDebitCapMismatch {
+                            participant: participant.name.clone(),
+                            currency: account.currency,
+                            expected: account.ndc,
+                            actual: current_ndc,
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(url) = &participant.endpoint {
+            let endpoints = match existing {
+                Some(_) => client.send(GetCallbackUrls { name: participant.name.clone() }).await?.des().await?,
+                None => Vec::new(),
+            };
+            let mismatched = mismatched_endpoints(endpoints.iter().map(|ep| (ep.r#type, ep.value.as_str())), url);
+            if !mismatched.is_empty() {
+                differences.push(Difference::EndpointMismatch {
+                    participant: participant.name.clone(),
+                    expected: url.clone(),
+                    actual: mismatched,
+                });
+            }
+        }
+    }
+
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mismatched(url: &str, value: impl Fn(FspiopCallbackType) -> String) -> Vec<EndpointValue> {
+        let endpoints: Vec<(FspiopCallbackType, String)> = FspiopCallbackType::iter().map(|t| (t, value(t))).collect();
+        mismatched_endpoints(endpoints.iter().map(|(t, v)| (*t, v.as_str())), &url::Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn endpoints_generated_from_url_match() {
+        assert!(mismatched("http://payerfsp.sim", |_| "http://payerfsp.sim".to_string()).is_empty());
+        assert!(mismatched("http://payerfsp.sim/fspiopapi/", |_| "http://payerfsp.sim/fspiopapi/transfers/{{transferId}}".to_string()).is_empty());
+    }
+
+    #[test]
+    fn endpoints_sharing_a_prefix_do_not_match() {
+        let first = FspiopCallbackType::iter().next().unwrap();
+        let result = mismatched("http://payerfsp", |t| {
+            if t == first { "http://payerfsp-sim/transfers".to_string() } else { "http://payerfsp/transfers".to_string() }
+        });
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].r#type, first);
+        assert_eq!(result[0].value.as_deref(), Some("http://payerfsp-sim/transfers"));
+    }
+
+    #[test]
+    fn missing_endpoints_mismatch() {
+        let url = url::Url::parse("http://payerfsp.sim").unwrap();
+        assert_eq!(mismatched_endpoints(std::iter::empty(), &url).len(), FspiopCallbackType::iter().count());
+    }
+}
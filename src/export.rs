@@ -0,0 +1,191 @@
+// Read the configuration of a live switch into a switch configuration document, such that it can
+// be supplied to the apply subcommand to recreate the configuration elsewhere.
+
+use mojaloop_api::{
+    central_ledger::participants::{
+        self,
+        AnyAccountType,
+        FspiopCallbackType,
+        GetCallbackUrls,
+        GetDfspAccounts,
+        GetParticipants,
+        LimitType,
+    },
+    central_ledger::settlement_models,
+    clients::FspiopClient,
+};
+
+use fspiox_api::Currency;
+
+use crate::clients::Clients;
+use crate::output::Output;
+use crate::switch_config::{
+    HubConfig,
+    ParticipantAccountConfig,
+    ParticipantConfig,
+    SettlementModelConfig,
+    SwitchConfig,
+};
+
+/// Recover the url supplied when a participant's endpoints were configured. Endpoints are supplied
+/// as (type, value) pairs. The transfer POST endpoint is always the supplied url with /transfers
+/// appended, so we use that.
+pub fn endpoint_base_url<'a>(
+    mut endpoints: impl Iterator<Item = (FspiopCallbackType, &'a str)>,
+) -> Option<url::Url> {
+    endpoints
+        .find(|(t, _)| *t == FspiopCallbackType::FspiopCallbackUrlTransferPost)
+        .and_then(|(_, value)| value.strip_suffix("/transfers"))
+        .and_then(|base| url::Url::parse(base).ok())
+}
+
+/// The accounts of a participant, from its position accounts as (currency, active) pairs and its
+/// net debit caps as (currency, value) pairs. Inactive accounts are not exported.
+pub fn participant_accounts(
+    accounts: impl Iterator<Item = (Currency, bool)>,
+    ndcs: &[(Currency, u32)],
+) -> Vec<ParticipantAccountConfig> {
+    let mut accounts: Vec<ParticipantAccountConfig> = accounts
+        .filter(|(_, active)| *active)
+        .map(|(currency, _)| ParticipantAccountConfig {
+            currency,
+            ndc: ndcs.iter().find(|(c, _)| *c == currency).map_or(0, |(_, value)| *value),
+            position: fspiox_api::Amount::ZERO,
+        })
+        .collect();
+    accounts.sort_by_key(|a| a.currency.to_string());
+    accounts
+}
+
+/// Read the current configuration of the switch. Account positions are not exported, as they're
+/// the result of transfers and funds in/out, not configuration. Inactive participant accounts are
+/// not exported. Warnings about anything that can't be exported are reported through `output`.
+pub async fn export(clients: &mut Clients, output: &Output) -> anyhow::Result<SwitchConfig> {
+    let hub = clients.hub();
+    let client = clients.central_ledger().await?;
+
+    let hub_accounts = client.send(GetDfspAccounts { name: hub.clone() }).await?.des().await?;
+    let mut hub_currencies = Vec::new();
+    for account in &hub_accounts {
+        let is_hub_account =
+            account.ledger_account_type == AnyAccountType::HubReconciliation ||
+            account.ledger_account_type == AnyAccountType::HubMultilateralSettlement;
+        if is_hub_account && !hub_currencies.contains(&account.currency) {
+            hub_currencies.push(account.currency);
+        }
+    }
+    hub_currencies.sort_by_key(|c| c.to_string());
+
+    let mut settlement_models: Vec<SettlementModelConfig> = client
+        .send(settlement_models::GetSettlementModels {}).await?.des().await?
+        .iter()
+        .map(SettlementModelConfig::from)
+        .collect();
+    settlement_models.sort_by_key(|m| (m.name.to_string(), m.currency.to_string()));
+
+    let mut participants = Vec::new();
+    let existing_participants = client.send(GetParticipants {}).await?.des().await?;
+    for participant in existing_participants.iter().filter(|p| p.name != hub) {
+        let limits = client.send(participants::GetParticipantLimits { name: participant.name.clone() }).await?.des().await?;
+        let ndcs: Vec<(Currency, u32)> = limits.iter()
+            .filter(|l| l.limit.r#type == LimitType::NetDebitCap)
+            .map(|l| (l.currency, l.limit.value))
+            .collect();
+        let accounts = participant_accounts(
+            participant.accounts.iter()
+                .filter(|a| a.ledger_account_type == AnyAccountType::Position)
+                .map(|a| (a.currency, a.is_active == 1)),
+            &ndcs,
+        );
+        if accounts.is_empty() {
+            output.warn(format!("participant {} has no active accounts, and will not be exported", participant.name));
+            continue;
+        }
+
+        let endpoints = client.send(GetCallbackUrls { name: participant.name.clone() }).await?.des().await?;
+        let endpoint = endpoint_base_url(endpoints.iter().map(|ep| (ep.r#type, ep.value.as_str())));
+        if endpoint.is_none() && !endpoints.is_empty() {
+            output.warn(format!(
+                "couldn't determine the endpoint url of participant {}, its endpoints will not be exported",
+                participant.name,
+            ));
+        }
+
+        participants.push(ParticipantConfig {
+            name: participant.name.clone(),
+            endpoint,
+            accounts,
+        });
+    }
+    participants.sort_by_key(|p| p.name.to_string());
+
+    Ok(SwitchConfig {
+        hub: HubConfig { currencies: hub_currencies },
+        settlement_models,
+        participants,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fspiox_api::FspId;
+
+    fn currency(s: &str) -> Currency {
+        s.parse().unwrap()
+    }
+
+    fn endpoints(base: &str) -> Vec<(FspiopCallbackType, String)> {
+        use strum::IntoEnumIterator;
+        FspiopCallbackType::iter().map(|t| (t, format!("{}/transfers", base))).collect()
+    }
+
+    #[test]
+    fn accounts() {
+        let accounts = participant_accounts(
+            vec![(currency("XOF"), true), (currency("EUR"), true), (currency("SEK"), false)].into_iter(),
+            &[(currency("XOF"), 10000), (currency("SEK"), 500)],
+        );
+        let accounts: Vec<(String, u32)> = accounts.iter().map(|a| (a.currency.to_string(), a.ndc)).collect();
+        assert_eq!(accounts, vec![("EUR".to_string(), 0), ("XOF".to_string(), 10000)]);
+    }
+
+    #[test]
+    fn base_url() {
+        let eps = endpoints("http://payerfsp.sim/fspiopapi");
+        let url = endpoint_base_url(eps.iter().map(|(t, v)| (*t, v.as_str())));
+        assert_eq!(url.map(|u| u.to_string()).as_deref(), Some("http://payerfsp.sim/fspiopapi"));
+        assert!(endpoint_base_url(std::iter::once((FspiopCallbackType::FspiopCallbackUrlTransferPost, "http://payerfsp.sim"))).is_none());
+        assert!(endpoint_base_url(std::iter::empty()).is_none());
+    }
+
+    #[test]
+    fn document() {
+        let eps = endpoints("http://payerfsp.sim/fspiopapi");
+        let config = SwitchConfig {
+            hub: HubConfig { currencies: vec![currency("XOF")] },
+            settlement_models: vec![SettlementModelConfig::deferred_net(currency("XOF")).unwrap()],
+            participants: vec![ParticipantConfig {
+                name: FspId::from("payerfsp").unwrap(),
+                endpoint: endpoint_base_url(eps.iter().map(|(t, v)| (*t, v.as_str()))),
+                accounts: participant_accounts(std::iter::once((currency("XOF"), true)), &[(currency("XOF"), 10000)]),
+            }],
+        };
+        for json in &[false, true] {
+            let document = config.serialise(*json).unwrap();
+            let parsed: SwitchConfig = if *json {
+                serde_json::from_str(&document).unwrap()
+            } else {
+                serde_yaml::from_str(&document).unwrap()
+            };
+            assert_eq!(parsed.serialise(*json).unwrap(), document);
+            let value: serde_json::Value = serde_json::to_value(&parsed).unwrap();
+            assert_eq!(value["hub"]["currencies"], serde_json::json!(["XOF"]));
+            assert_eq!(value["settlement_models"][0]["name"], "DEFERREDNETXOF");
+            assert_eq!(value["participants"][0]["name"], "payerfsp");
+            assert_eq!(value["participants"][0]["endpoint"], "http://payerfsp.sim/fspiopapi");
+            assert_eq!(value["participants"][0]["accounts"][0]["ndc"], 10000);
+        }
+    }
+}
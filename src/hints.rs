@@ -0,0 +1,390 @@
+// Remediation hints. Many errors returned by the switch are the result of missing or conflicting
+// configuration that this cli can itself create or inspect, but the switch describes them only
+// with an error code and a terse description. Where we recognise an error we explain it in plain
+// language and, where there is one, suggest the mojaloop-cli command that resolves it.
+//
+// The switch doesn't tell us which participant or currency an error concerns, so we take those
+// from the operation that failed (see operations::Failed) or, for errors reported asynchronously,
+// from the command being run.
+
+use serde::Serialize;
+
+use fspiox_api::{Currency, FspId};
+use mojaloop_api::central_ledger::participants::HubAccountType;
+
+use crate::operations::{Failed, Operation};
+use crate::MojaloopCliError;
+
+/// What was being attempted when an error occurred
+#[derive(Debug, Clone, Default)]
+pub struct Context<'a> {
+    pub operation: Option<&'a Operation>,
+    pub participant: Option<FspId>,
+    pub currency: Option<Currency>,
+}
+
+impl<'a> From<&'a Operation> for Context<'a> {
+    fn from(op: &'a Operation) -> Context<'a> {
+        let (participant, currency) = match op {
+            Operation::CreateHubAccount { currency, .. } => (None, Some(*currency)),
+            Operation::CreateSettlementModel { settlement_model } => (None, Some(settlement_model.currency)),
+            Operation::CreateParticipantAccount { participant, currency } |
+            Operation::SetInitialPositionAndLimits { participant, currency, .. } |
+            Operation::SetAccountActive { participant, currency, .. } |
+            Operation::SetNetDebitCap { participant, currency, .. } =>
+                (Some(participant.clone()), Some(*currency)),
+            Operation::RecordFunds { participant, amount, .. } =>
+                (Some(participant.clone()), Some(amount.currency)),
+            Operation::SetEndpoint { participant, .. } |
+            Operation::FinaliseFundsOut { participant, .. } => (Some(participant.clone()), None),
+            Operation::CloseSettlementWindow { .. } |
+            Operation::CreateSettlement { .. } |
+            Operation::UpdateSettlementAccounts { .. } => (None, None),
+        };
+        Context { operation: Some(op), participant, currency }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Hint {
+    pub explanation: String,
+    /// The mojaloop-cli arguments that resolve the problem. Arguments we can't determine are
+    /// written as placeholders, e.g. <currency>.
+    #[serde(serialize_with = "serialize_command")]
+    pub command: Option<Vec<String>>,
+}
+
+fn serialize_command<S: serde::Serializer>(command: &Option<Vec<String>>, s: S) -> Result<S::Ok, S::Error> {
+    match command {
+        Some(args) => s.serialize_some(&command_line(args)),
+        None => s.serialize_none(),
+    }
+}
+
+fn command_line(args: &[String]) -> String {
+    std::iter::once("mojaloop-cli").chain(args.iter().map(|a| a.as_str())).collect::<Vec<_>>().join(" ")
+}
+
+impl Hint {
+    fn new(explanation: impl Into<String>, command: &[&str]) -> Hint {
+        Hint {
+            explanation: explanation.into(),
+            command: Some(command.iter().map(|a| a.to_string()).collect()),
+        }
+    }
+
+    /// Whether the command can be run as it stands, i.e. has no placeholders
+    pub fn runnable(&self) -> bool {
+        self.command.as_ref().map_or(false, |args| !args.iter().any(|a| a.starts_with('<')))
+    }
+}
+
+impl std::fmt::Display for Hint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.explanation)?;
+        if let Some(args) = &self.command {
+            write!(f, "\n\n    {}", command_line(args))?;
+        }
+        Ok(())
+    }
+}
+
+/// The code and description of a Mojaloop API error
+fn error_information(value: &serde_json::Value) -> Option<(String, String)> {
+    let info = value.get("errorInformation")?;
+    let code = match info.get("errorCode")? {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => return None,
+    };
+    let description = info.get("errorDescription").and_then(|d| d.as_str()).unwrap_or_default();
+    Some((code, description.to_string()))
+}
+
+/// Find a Mojaloop API error anywhere in a JSON document, for example a message from the voodoo
+/// doll wrapping the error callback of a transfer
+fn find_error_information(value: &serde_json::Value) -> Option<(String, String)> {
+    error_information(value).or_else(|| match value {
+        serde_json::Value::Object(map) => map.values().find_map(find_error_information),
+        serde_json::Value::Array(items) => items.iter().find_map(find_error_information),
+        _ => None,
+    })
+}
+
+fn hub_account_missing(description: &str) -> bool {
+    let desc = description.to_lowercase();
+    desc.contains("hub reconciliation account") || desc.contains("hub multilateral settlement account")
+}
+
+/// Whether an error was caused by the hub lacking accounts in the currency of the operation
+pub fn hub_accounts_missing(err: &anyhow::Error) -> bool {
+    api_error_description(err).map_or(false, |description| hub_account_missing(&description))
+}
+
+/// The code and description of the Mojaloop API error that caused an error, if there was one
+fn api_error(err: &anyhow::Error) -> Option<(String, String)> {
+    crate::output::mojaloop_error(err)
+        .and_then(|response| serde_json::to_value(response).ok())
+        .and_then(|value| error_information(&value))
+}
+
+/// The description of the Mojaloop API error that caused an error, if there was one
+fn api_error_description(err: &anyhow::Error) -> Option<String> {
+    api_error(err).map(|(_, description)| description)
+}
+
+/// Whether an error description suggests a conflict with something that exists. Only used to
+/// choose an explanation; see `duplicate_of` for deciding whether an operation can be skipped.
+fn duplicate(description: &str) -> bool {
+    let desc = description.to_lowercase();
+    ["already been registered", "already exists", "already set", "duplicate"].iter().any(|s| desc.contains(s))
+}
+
+/// Whether an error is the one central-ledger returns when the given operation would create
+/// something that already exists. Each operation has a specific error code and description, and
+/// nothing else is taken to mean the thing exists.
+fn duplicate_of(operation: &Operation, code: &str, description: &str) -> bool {
+    let (codes, message): (&[&str], &str) = match operation {
+        Operation::CreateHubAccount { .. } => (&["3003"], "hub account has already been registered"),
+        Operation::CreateParticipantAccount { .. } => (&["3003"], "participant currency has already been registered"),
+        // Reported as an internal server error by some central-ledger versions
+        Operation::CreateSettlementModel { .. } => (&["2001", "3003"], "settlement model already exists"),
+        _ => return false,
+    };
+    codes.contains(&code) && description.to_lowercase().contains(message)
+}
+
+/// Whether an error was caused by an attempt by the given operation to create something that
+/// already exists
+pub fn already_exists(operation: &Operation, err: &anyhow::Error) -> bool {
+    api_error(err).map_or(false, |(code, description)| duplicate_of(operation, &code, &description))
+}
+
+/// A hint for a Mojaloop API error
+pub fn explain(code: &str, description: &str, context: &Context) -> Option<Hint> {
+    let desc = description.to_lowercase();
+    let currency = context.currency.map_or_else(|| "<currency>".to_string(), |c| c.to_string());
+    let participant = context.participant.as_ref().map_or_else(|| "<participant>".to_string(), |p| p.to_string());
+    let missing = desc.contains("does not exist") || desc.contains("not found") || desc.contains("unable to find");
+
+    if hub_account_missing(&desc) {
+        return Some(Hint::new(
+            format!(
+                "The hub has no {} reconciliation or multilateral settlement account. The hub \
+                requires both before participants can hold accounts in {}. Create them with:",
+                currency,
+                currency,
+            ),
+            &["hub", "accounts", "create", "all", currency.as_str()],
+        ));
+    }
+
+    // Reported with the generic validation error code, 3100, so we rely on the description
+    if desc.contains("settlement model") && missing {
+        return Some(Hint::new(
+            format!(
+                "There is no settlement model for {}. Participant accounts in a currency require a \
+                settlement model for that currency. Create one with:",
+                currency,
+            ),
+            &["hub", "settlement-model", "create", currency.as_str()],
+        ));
+    }
+
+    if duplicate(&desc) {
+        return Some(match context.operation {
+            Some(Operation::CreateHubAccount { r#type, .. }) => Hint::new(
+                format!(
+                    "The hub already has a {} {} account. List hub accounts with:",
+                    currency,
+                    match r#type {
+                        HubAccountType::HubMultilateralSettlement => "multilateral settlement",
+                        HubAccountType::HubReconciliation => "reconciliation",
+                    },
+                ),
+                &["hub", "accounts", "list"],
+            ),
+            Some(Operation::CreateSettlementModel { settlement_model }) => Hint::new(
+                format!(
+                    "Settlement model {} already exists. List settlement models with:",
+                    settlement_model.name,
+                ),
+                &["hub", "settlement-model", "list"],
+            ),
+            _ => Hint::new(
+                format!(
+                    "Participant {} already has a {} account. To create the account only where it \
+                    doesn't exist, and set its NDC, use:",
+                    participant,
+                    currency,
+                ),
+                &["participant", participant.as_str(), "accounts", "upsert", currency.as_str()],
+            ),
+        });
+    }
+
+    // Payer limit error: the transfer would take the payer's position beyond its net debit cap
+    if code == "4200" || desc.contains("limit error") {
+        return Some(Hint::new(
+            format!(
+                "This transfer would take the {} position of {} beyond its net debit cap. Raise \
+                the net debit cap with:",
+                currency,
+                participant,
+            ),
+            &["participant", participant.as_str(), "limits", "set", currency.as_str(), "<ndc>"],
+        ));
+    }
+
+    // Payer insufficient liquidity: the payer hasn't deposited enough funds to cover the transfer
+    if code == "4001" || desc.contains("insufficient liquidity") {
+        return Some(Hint::new(
+            format!(
+                "{} has insufficient {} liquidity for this transfer. Fund its settlement account \
+                with:",
+                participant,
+                currency,
+            ),
+            &["participant", participant.as_str(), "accounts", "fund", currency.as_str(), "in", "<amount>"],
+        ));
+    }
+
+    if desc.contains("account") && missing && context.participant.is_some() {
+        return Some(Hint::new(
+            format!("{} has no {} account. Create one with:", participant, currency),
+            &["participant", participant.as_str(), "accounts", "upsert", currency.as_str()],
+        ));
+    }
+
+    None
+}
+
+/// A hint for an error returned by a command, if we recognise it
+pub fn hint(err: &anyhow::Error) -> Option<Hint> {
+    if let Some(MojaloopCliError::ParticipantMissingCurrencyAccount(participant, currency)) =
+        err.chain().find_map(|e| e.downcast_ref::<MojaloopCliError>())
+    {
+        let (participant, currency) = (participant.to_string(), currency.to_string());
+        return Some(Hint::new(
+            format!("{} has no {} account. Create one with:", participant, currency),
+            &["participant", participant.as_str(), "accounts", "upsert", currency.as_str()],
+        ));
+    }
+    let response = crate::output::mojaloop_error(err)?;
+    let (code, description) = error_information(&serde_json::to_value(response).ok()?)?;
+    let context = err.chain()
+        .find_map(|e| e.downcast_ref::<Failed>())
+        .map_or_else(Context::default, |Failed(op)| Context::from(op));
+    explain(&code, &description, &context)
+}
+
+/// A hint for an error reported asynchronously, for example in a transfer error callback
+pub fn hint_for_value(value: &serde_json::Value, context: &Context) -> Option<Hint> {
+    let (code, description) = find_error_information(value)?;
+    explain(&code, &description, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency() -> Currency {
+        "XOF".parse().unwrap()
+    }
+
+    fn participant() -> FspId {
+        FspId::from("payerfsp").unwrap()
+    }
+
+    #[test]
+    fn duplicates_by_operation() {
+        let hub_account = Operation::CreateHubAccount { r#type: HubAccountType::HubReconciliation, currency: currency() };
+        let participant_account = Operation::CreateParticipantAccount { participant: participant(), currency: currency() };
+        let limits = Operation::SetInitialPositionAndLimits {
+            participant: participant(),
+            currency: currency(),
+            ndc: 0,
+            position: fspiox_api::Amount::ZERO,
+        };
+        let cases: &[(&Operation, &str, &str, bool)] = &[
+            (&hub_account, "3003", "Add Party information error - Hub account has already been registered.", true),
+            (&hub_account, "3100", "Generic validation error - Hub account has already been registered.", false),
+            (&hub_account, "3003", "Add Party information error - Participant currency has already been registered", false),
+            (&hub_account, "3003", "Duplicate entry", false),
+            (&participant_account, "3003", "Add Party information error - Participant currency has already been registered", true),
+            (&participant_account, "3003", "Add Party information error - Hub account has already been registered.", false),
+            (&limits, "3003", "Add Party information error - Participant Limit already set", false),
+            (&limits, "3003", "Add Party information error - Participant currency has already been registered", false),
+        ];
+        for (operation, code, description, expected) in cases {
+            assert_eq!(duplicate_of(operation, code, description), *expected, "{} {}: {}", operation, code, description);
+        }
+    }
+
+    fn command(hint: Option<Hint>) -> Option<String> {
+        hint.and_then(|h| h.command).map(|args| args.join(" "))
+    }
+
+    #[test]
+    fn explain_errors() {
+        let hub_account = Operation::CreateHubAccount { r#type: HubAccountType::HubMultilateralSettlement, currency: currency() };
+        let settlement_model = Operation::CreateSettlementModel {
+            settlement_model: (&crate::switch_config::SettlementModelConfig::deferred_net(currency()).unwrap()).into(),
+        };
+        let none = Context::default();
+        let with_currency = Context { currency: Some(currency()), ..Context::default() };
+        let with_participant = Context { participant: Some(participant()), currency: Some(currency()), ..Context::default() };
+        let creating_hub_account = Context::from(&hub_account);
+        let creating_settlement_model = Context::from(&settlement_model);
+        let cases: &[(&str, &str, &Context, Option<&str>)] = &[
+            ("3000", "Generic server error - Hub reconciliation account for the specified currency does not exist", &with_currency, Some("hub accounts create all XOF")),
+            ("3000", "Hub multilateral settlement account does not exist", &none, Some("hub accounts create all <currency>")),
+            ("3100", "Generic validation error - Unable to find a matching or default, Settlement Model", &with_currency, Some("hub settlement-model create XOF")),
+            ("3100", "Generic validation error - .body.amount should match pattern", &with_currency, None),
+            ("3100", "Generic validation error - settlement model is invalid", &with_currency, None),
+            ("3003", "Add Party information error - Hub account has already been registered.", &creating_hub_account, Some("hub accounts list")),
+            ("3003", "Settlement model already exists", &creating_settlement_model, Some("hub settlement-model list")),
+            ("3003", "Add Party information error - Participant currency has already been registered", &with_participant, Some("participant payerfsp accounts upsert XOF")),
+            ("4200", "Payer limit error", &with_participant, Some("participant payerfsp limits set XOF <ndc>")),
+            ("4000", "Payer FSP limit error", &none, Some("participant <participant> limits set <currency> <ndc>")),
+            ("4001", "Payer FSP insufficient liquidity", &with_participant, Some("participant payerfsp accounts fund XOF in <amount>")),
+            ("3200", "Participant account does not exist", &with_participant, Some("participant payerfsp accounts upsert XOF")),
+            ("3200", "Participant account does not exist", &none, None),
+            ("2001", "Internal server error", &with_participant, None),
+        ];
+        for (code, description, context, expected) in cases {
+            assert_eq!(command(explain(code, description, context)).as_deref(), *expected, "{}: {}", code, description);
+        }
+    }
+
+    #[test]
+    fn find_nested_error_information() {
+        let cases = vec![
+            (serde_json::json!({ "errorInformation": { "errorCode": "4001", "errorDescription": "Payer FSP insufficient liquidity" } }),
+                Some(("4001".to_string(), "Payer FSP insufficient liquidity".to_string()))),
+            (serde_json::json!({ "TransferError": { "response": { "errorInformation": { "errorCode": 4200, "errorDescription": "Payer limit error" } } } }),
+                Some(("4200".to_string(), "Payer limit error".to_string()))),
+            (serde_json::json!([{ "other": 1 }, { "errorInformation": { "errorCode": "3100" } }]),
+                Some(("3100".to_string(), String::new()))),
+            (serde_json::json!({ "errorInformation": { "errorCode": true } }), None),
+            (serde_json::json!({ "transferState": "COMMITTED" }), None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(find_error_information(&value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn hint_for_async_errors() {
+        let context = Context { participant: Some(participant()), currency: Some(currency()), ..Context::default() };
+        let error = serde_json::json!({
+            "TransferError": { "response": { "errorInformation": { "errorCode": "4001", "errorDescription": "Payer FSP insufficient liquidity" } } },
+        });
+        assert_eq!(
+            command(hint_for_value(&error, &context)).as_deref(),
+            Some("participant payerfsp accounts fund XOF in <amount>"),
+        );
+        let committed = serde_json::json!({ "TransferComplete": { "id": "x" } });
+        assert!(hint_for_value(&committed, &context).is_none());
+    }
+}
@@ -23,7 +23,6 @@
 //   - configure endpoints
 // - settlements
 
-use strum::IntoEnumIterator;
 use strum_macros::Display;
 
 use mojaloop_api::{
@@ -34,20 +33,12 @@ use mojaloop_api::{
         GetCallbackUrls,
         GetDfspAccounts,
         GetParticipants,
-        HubAccount,
         HubAccountType,
-        InitialPositionAndLimits,
-        Limit,
-        LimitType,
-        NewParticipant,
-        PostCallbackUrl,
-        PostHubAccount,
-        PostInitialPositionAndLimits,
-        PostParticipant,
-        PutParticipantAccount,
     },
     central_ledger::settlement_models,
-    settlement::{settlement_windows, settlement},
+    central_ledger::transfers as central_ledger_transfers,
+    clients::FspiopClient,
+    settlement::{settlement, settlement_windows},
 };
 use fspiox_api::{
     FspiopRequestBody, Amount, Currency, FspId, ErrorResponse, CorrelationId, transfer, quote,
@@ -65,6 +56,22 @@ use cli_table::{print_stdout, Cell, Table};
 
 use std::convert::TryFrom;
 
+mod amount;
+mod apply;
+mod clients;
+mod currency;
+mod diff;
+mod export;
+mod hints;
+mod operations;
+mod output;
+mod profile;
+mod prompt;
+mod settlements;
+mod switch_config;
+use clients::Clients;
+use output::Output;
+
 #[derive(Clap)]
 #[clap(
     setting = clap::AppSettings::ArgRequiredElseHelp,
@@ -75,14 +82,36 @@ use std::convert::TryFrom;
 struct Opts {
     // TODO: overall timeout? Probably. Remember how annoying `kubectl wait` is, with its
     // per-request timeout, meaning that you could wait up to n*timeout to wait for n items.
-    /// Per-request timeout. A single command may make multiple requests.
-    #[clap(short, long, default_value = "30")]
-    timeout: u8,
+    /// Per-request timeout. A single command may make multiple requests. Also the time, in
+    /// seconds, settle waits for the settlement window to close. [default: 30]
+    #[clap(short, long)]
+    timeout: Option<u8>,
+
+    /// The profile in the configuration file from which to take defaults for these options. May
+    /// also be supplied as MOJALOOP_PROFILE. See the README for the configuration file format.
+    #[clap(short, long)]
+    profile: Option<String>,
+
+    /// The name of the hub participant. [default: Hub]
+    #[clap(long)]
+    hub_name: Option<FspId>,
 
     /// Location of the kubeconfig file to use
     #[clap(short, long)]
     kubeconfig: Option<std::path::PathBuf>,
 
+    /// The kubeconfig context to use. Defaults to the current context.
+    #[clap(long)]
+    context: Option<String>,
+
+    /// The kubeconfig cluster to use. Defaults to the cluster of the context.
+    #[clap(long)]
+    cluster: Option<String>,
+
+    /// The kubeconfig user to use. Defaults to the user of the context.
+    #[clap(long)]
+    user: Option<String>,
+
     // TODO: all namespace option? Don't have a reserved "all" argument i.e. --namespace=all,
     // because someone could call their real namespace "all". Probably try to go with common k8s
     // flags for this, perhaps -A and --all-namespaces (check those are correct).
@@ -91,10 +120,37 @@ struct Opts {
     #[clap(short, long)]
     namespace: Option<String>,
 
+    /// Base url of central-ledger. Supplying the url of any service connects to all services
+    /// directly, without Kubernetes. May also be supplied as MOJALOOP_CENTRAL_LEDGER_URL.
+    #[clap(long)]
+    central_ledger_url: Option<url::Url>,
+
+    /// Base url of central-settlement. May also be supplied as MOJALOOP_SETTLEMENT_URL.
+    #[clap(long)]
+    settlement_url: Option<url::Url>,
+
+    /// Base url of quoting-service. May also be supplied as MOJALOOP_QUOTE_URL.
+    #[clap(long)]
+    quote_url: Option<url::Url>,
+
+    /// Base url of ml-api-adapter. May also be supplied as MOJALOOP_TRANSFER_URL.
+    #[clap(long)]
+    transfer_url: Option<url::Url>,
+
     /// Produce all output as json
     #[clap(short, long)]
     json: bool,
 
+    /// Print the operations a command would perform against the switch, without performing them.
+    /// Reads from the switch are still performed, so that the operations can be determined.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Fail where something a command would create already exists. By default, anything that
+    /// already exists is skipped, so that commands can be safely re-run.
+    #[clap(long)]
+    strict: bool,
+
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
@@ -129,14 +185,86 @@ enum SubCommand {
     Quote(Quote),
     /// Create and manage settlements and settlement windows
     Settlement(Settlement),
+    /// Close the open settlement window, create a settlement from it, and move every account in
+    /// that settlement to SETTLED
+    Settle(Settle),
     /// Complex behaviours and scenarios that require a component deployed to the cluster to
     /// simulate participants.
     Voodoo(Voodoo),
+    /// Converge the switch to the state described in a switch configuration file
+    Apply(Apply),
+    /// Print the configuration of the switch as a switch configuration file, for use with apply
+    Export(Export),
+    /// Show the differences between a switch configuration file and the switch
+    Diff(Diff),
+    /// Set up a switch with hub accounts and a DEFERREDNET<CURRENCY> settlement model in each of
+    /// the given currencies, and participants with an account in each. Anything that already exists is left
+    /// as it is.
+    QuickStart(QuickStart),
     // /// Onboard a participant
     // #[clap(alias = "ob")]
     // Onboard(Onboard),
 }
 
+#[derive(Clap)]
+struct Apply {
+    /// A YAML or JSON switch configuration file. Files with a .json extension will be parsed as
+    /// JSON, all others as YAML. See the README for the format of this file.
+    #[clap(short, long)]
+    file: std::path::PathBuf,
+}
+
+#[derive(Clap)]
+struct Diff {
+    /// A YAML or JSON switch configuration file, as supplied to apply
+    #[clap(short, long)]
+    file: std::path::PathBuf,
+    /// Exit with status 1 if there are differences, and 0 otherwise
+    #[clap(long)]
+    exit_code: bool,
+}
+
+#[derive(Clap)]
+struct QuickStart {
+    /// A currency to set up. May be supplied multiple times. May be omitted where a default
+    /// currency is configured.
+    #[clap(short, long, multiple_occurrences = true, parse(try_from_str = currency::parse_currency))]
+    currency: Vec<Currency>,
+    /// A participant to onboard. May be supplied multiple times.
+    #[clap(short, long, required = true, multiple_occurrences = true)]
+    participant: Vec<FspId>,
+    /// The net debit cap of each participant account
+    #[clap(short, long, default_value = "10000", parse(try_from_str = amount::parse_ndc))]
+    ndc: u32,
+    /// The initial position of each participant account
+    #[clap(long, default_value = "0", parse(try_from_str = amount::parse_amount))]
+    position: Amount,
+    /// The host to which FSPIOP requests destined for each participant will be delivered. {fsp}
+    /// is replaced with the participant name.
+    #[clap(short, long, default_value = "http://{fsp}.sim/fspiopapi")]
+    endpoint: String,
+}
+
+#[derive(Clap)]
+struct Export {
+    /// Write the switch configuration to this file instead of stdout. The configuration will be
+    /// written as JSON if the file has a .json extension or --json is supplied, otherwise as YAML.
+    #[clap(short, long)]
+    file: Option<std::path::PathBuf>,
+}
+
+#[derive(Clap)]
+struct Settle {
+    #[clap(short, long, default_value = "Mojaloop CLI request")]
+    reason: String,
+    /// A reference to the settlement in an external system, recorded against each account
+    #[clap(short, long, default_value = "Mojaloop CLI request")]
+    external_reference: String,
+    /// The settlement model with which to create the settlement
+    #[clap(index = 1, default_value = "DEFERREDNET")]
+    settlement_model: String,
+}
+
 #[derive(Clap)]
 struct Settlement {
     #[clap(subcommand)]
@@ -151,7 +279,21 @@ enum SettlementSubCommand {
     /// Settlement window commands
     #[clap(alias = "win", alias = "windows")]
     Window(SettlementWindow),
-    // TODO: settlement model subcommand
+    /// Show a settlement by ID
+    Get(SettlementGet),
+    /// List settlements. The settlement service requires at least one filter.
+    #[clap(alias = "ls")]
+    List(SettlementList),
+    /// Produce a report of the net position of each participant account in a settlement, for
+    /// example to send to a settlement bank
+    Report(SettlementReport),
+    /// Move the participant accounts of a settlement through the settlement lifecycle.
+    ///
+    /// Participant accounts must pass through the states PS_TRANSFERS_RECORDED,
+    /// PS_TRANSFERS_RESERVED, PS_TRANSFERS_COMMITTED and SETTLED in order. Each account is moved
+    /// through every state between its current state and the target state. Accounts already at or
+    /// beyond the target state are not modified.
+    Advance(SettlementAdvance),
 }
 
 #[derive(Clap)]
@@ -179,11 +321,23 @@ struct GetSettlementWindow {
 
 #[derive(Clap)]
 struct FilterSettlementWindows {
-    // TODO: should support multiple states
-    /// The settlement window state
-    #[clap(default_value = "OPEN")]
-    state: settlement_windows::SettlementWindowState,
-    // TODO: should support other filter options
+    /// Only show windows in this state. May be supplied multiple times. Where no filters are
+    /// supplied, OPEN windows are shown.
+    #[clap(short, long, multiple_occurrences = true)]
+    state: Vec<settlement_windows::SettlementWindowState>,
+    /// Only show windows with content in this currency
+    #[clap(short, long, parse(try_from_str = currency::parse_currency))]
+    currency: Option<Currency>,
+    /// Only show windows containing this participant. This is the numeric participant ID shown by
+    /// `settlement get`.
+    #[clap(short, long)]
+    participant_id: Option<settlement::ParticipantId>,
+    /// Only show windows created at or after this time, e.g. 2021-09-01T00:00:00Z
+    #[clap(short, long)]
+    from: Option<fspiox_api::DateTime>,
+    /// Only show windows created at or before this time, e.g. 2021-09-02T00:00:00Z
+    #[clap(short, long)]
+    to: Option<fspiox_api::DateTime>,
 }
 
 #[derive(Clap)]
@@ -204,6 +358,61 @@ struct SettlementCreate {
     settlement_window_ids: Vec<settlement_windows::SettlementWindowId>,
 }
 
+#[derive(Clap)]
+struct SettlementGet {
+    #[clap(index = 1, required = true)]
+    id: settlement::SettlementId,
+}
+
+#[derive(Clap)]
+struct SettlementList {
+    /// Only list settlements in this state
+    #[clap(short, long)]
+    state: Option<settlement::SettlementState>,
+    /// Only list settlements in this currency
+    #[clap(short, long, parse(try_from_str = currency::parse_currency))]
+    currency: Option<Currency>,
+    /// Only list settlements containing this participant. This is the numeric participant ID
+    /// shown by `settlement get`.
+    #[clap(short, long)]
+    participant_id: Option<settlement::ParticipantId>,
+    /// Only list settlements created at or after this time, e.g. 2021-09-01T00:00:00Z
+    #[clap(short, long)]
+    from: Option<fspiox_api::DateTime>,
+    /// Only list settlements created at or before this time, e.g. 2021-09-02T00:00:00Z
+    #[clap(short, long)]
+    to: Option<fspiox_api::DateTime>,
+}
+
+#[derive(Clap)]
+struct SettlementReport {
+    /// The report format. One of table, csv, json. Defaults to json when --json is supplied.
+    #[clap(short, long, default_value = "table")]
+    format: settlements::ReportFormat,
+    /// Write the report to this file instead of stdout
+    #[clap(short, long)]
+    output: Option<std::path::PathBuf>,
+    #[clap(index = 1, required = true)]
+    id: settlement::SettlementId,
+}
+
+#[derive(Clap)]
+struct SettlementAdvance {
+    #[clap(short, long, default_value = "Mojaloop CLI request")]
+    reason: String,
+    #[clap(short, long, default_value = "Mojaloop CLI request")]
+    external_reference: String,
+    /// Only move the accounts of this participant. May be supplied multiple times.
+    #[clap(short, long, multiple_occurrences = true)]
+    participant: Vec<FspId>,
+    #[clap(index = 1, required = true)]
+    id: settlement::SettlementId,
+    /// The state to which accounts will be moved. One of PS_TRANSFERS_RECORDED,
+    /// PS_TRANSFERS_RESERVED, PS_TRANSFERS_COMMITTED, SETTLED.
+    #[clap(index = 2, default_value = "SETTLED")]
+    state: settlement::SettlementState,
+}
+
 // #[derive(Clap)]
 // struct Onboard {
 //     #[clap(index = 1, required = true)]
@@ -227,8 +436,22 @@ struct Quote {
 
 #[derive(Clap)]
 enum QuoteSubCommand {
-    #[clap(alias = "new")]
+    #[clap(alias = "new", setting = clap::AppSettings::AllowMissingPositional)]
     Create(QuoteCreate),
+    /// Request (GET) a quote, acting as a given FSP.
+    ///
+    /// Note that the switch responds to the FSP asynchronously, at its quotes endpoint. This
+    /// command sends the request and prints that endpoint, it does not receive the response.
+    Get(QuoteGet),
+}
+
+#[derive(Clap)]
+struct QuoteGet {
+    /// The FSP as which to send the request
+    #[clap(index = 1, required = true)]
+    fsp: FspId,
+    #[clap(index = 2, required = true)]
+    id: quote::QuoteId,
 }
 
 #[derive(Clap)]
@@ -237,10 +460,11 @@ struct QuoteCreate {
     from: FspId,
     #[clap(index = 2, required = true)]
     to: FspId,
-    #[clap(index = 3, required = true)]
-    currency: fspiox_api::Currency,
+    /// May be omitted where a default currency is configured
+    #[clap(index = 3, parse(try_from_str = currency::parse_currency))]
+    currency: Option<fspiox_api::Currency>,
     // TODO: take multiple
-    #[clap(index = 4, required = true)]
+    #[clap(index = 4, required = true, parse(try_from_str = amount::parse_amount))]
     amount: Amount,
 }
 
@@ -296,13 +520,30 @@ enum VoodooSubCommand {
     Transfer(PuppetTransfer),
 }
 
+// As with ParticipantOnboard, the currency argument is followed by a required argument, so we
+// parse the arguments following the payee ourselves.
 #[derive(Clap, PartialEq, Eq, Clone)]
 struct PuppetTransfer {
     payer: FspId,
     payee: FspId,
-    currency: fspiox_api::Currency,
-    amount: Amount,
-    transfer_id: Option<transfer::TransferId>,
+    /// [CURRENCY] AMOUNT [TRANSFER_ID]
+    ///
+    /// The currency may be omitted where a default currency is configured.
+    #[clap(required = true, min_values = 1, max_values = 3, value_name = "ARGS")]
+    args: Vec<String>,
+}
+
+impl PuppetTransfer {
+    fn parse(&self, default_currency: Option<Currency>) -> Result<(Currency, Amount, Option<transfer::TransferId>), MojaloopCliError> {
+        let (currency, args) = split_currency(&self.args, default_currency, |a| amount::parse_amount(a).is_ok())?;
+        if let Some(unexpected) = args.get(2) {
+            return Err(MojaloopCliError::UnexpectedArgument(unexpected.clone()));
+        }
+        let amount = parse_arg("AMOUNT", args.get(0), amount::parse_amount)?
+            .ok_or_else(|| MojaloopCliError::MissingArgument("AMOUNT".to_string()))?;
+        let transfer_id = parse_arg("TRANSFER_ID", args.get(1), |s| s.parse::<transfer::TransferId>())?;
+        Ok((currency, amount, transfer_id))
+    }
 }
 
 #[derive(Clap)]
@@ -318,17 +559,23 @@ enum TransferSubCommand {
     /// The transfer correlation ID will be generated and printed as output.
     #[clap(alias = "post")]
     Prepare(TransferPrepare),
-    // /// Fulfil (PUT) a transfer.
-    // ///
-    // /// You'll probably want to use a correlation ID from a transfer prepare here.
-    // #[clap(alias = "put")]
-    // Fulfil(TransferFulfil),
-    // /// Prepare and fulfil a transfer.
-    // ///
-    // /// This command has no way of determining whether a transfer prepare has been received by the
-    // /// intended recipient. It therefore waits a configurable amount of time for this to occur
-    // /// between sending prepares and fulfils.
-    // PrepareFulfil(TransferPrepareFulfil),
+    /// Fulfil (PUT) a transfer, acting as the payee.
+    ///
+    /// You'll probably want to use a transfer ID from a transfer prepare here.
+    #[clap(alias = "put")]
+    Fulfil(TransferFulfil),
+    /// Prepare and fulfil a transfer.
+    ///
+    /// This command has no way of determining whether a transfer prepare has been received by the
+    /// intended recipient. It therefore waits a configurable amount of time for this to occur
+    /// between sending prepares and fulfils.
+    PrepareFulfil(TransferPrepareFulfil),
+    /// Request (GET) a transfer, acting as a given FSP.
+    ///
+    /// Note that the switch responds to the FSP asynchronously, at its transfer PUT endpoint. This
+    /// command sends the request and prints that endpoint, it does not receive the response. To
+    /// see the state of the transfer, supply --admin.
+    Get(TransferGet),
 }
 
 #[derive(Clap)]
@@ -340,6 +587,7 @@ struct TransferPrepare {
 #[derive(Clap)]
 enum TransferPrepareSubCommand {
     /// Prepare a transfer without an existing transaction ID
+    #[clap(setting = clap::AppSettings::AllowMissingPositional)]
     New(TransferPrepareNew),
     /// Prepare a transfer from an existing quote transaction ID
     ///
@@ -360,7 +608,7 @@ struct TransferPrepareWithId {
     from: FspId,
     #[clap(index = 2, required = true)]
     to: FspId,
-    #[clap(index = 3, required = true)]
+    #[clap(index = 3, required = true, parse(try_from_str = currency::parse_currency))]
     currency: fspiox_api::Currency,
     // TODO: it might be possible to put these under flags or a subcommand or similar to allow
     // multiple. I.e. we might be able to say
@@ -369,7 +617,7 @@ struct TransferPrepareWithId {
     //     send 200 e1f3c512-dd8e-4b5b-ad59-4e87bf97fcb8 \
     //     ...
     // or similar
-    #[clap(index = 4, required = true)]
+    #[clap(index = 4, required = true, parse(try_from_str = amount::parse_amount))]
     amount: Amount,
     #[clap(index = 5, required = true)]
     transfer_id: transfer::TransferId,
@@ -381,19 +629,40 @@ struct TransferPrepareNew {
     from: FspId,
     #[clap(index = 2, required = true)]
     to: FspId,
-    #[clap(index = 3, required = true)]
-    currency: Currency,
+    /// May be omitted where a default currency is configured
+    #[clap(index = 3, parse(try_from_str = currency::parse_currency))]
+    currency: Option<Currency>,
     // TODO: take multiple
-    #[clap(index = 4, required = true)]
+    #[clap(index = 4, required = true, parse(try_from_str = amount::parse_amount))]
     amount: Amount,
 }
 
+#[derive(Clap)]
+struct TransferGet {
+    /// The FSP as which to send the request. Must be the payer or payee of the transfer.
+    #[clap(index = 1, required = true)]
+    fsp: FspId,
+    #[clap(index = 2, required = true)]
+    id: transfer::TransferId,
+    /// Also read the transfer from the central-ledger admin API, and print its state, e.g.
+    /// RESERVED, COMMITTED or ABORTED
+    #[clap(long)]
+    admin: bool,
+}
+
 #[derive(Clap)]
 struct TransferFulfil {
-    #[clap(index = 1, default_value = "COMMITTED")]
+    /// The payer of the transfer, to which the fulfil will be sent
+    #[clap(index = 1, required = true)]
+    from: FspId,
+    /// The payee of the transfer, as which the fulfil will be sent
+    #[clap(index = 2, required = true)]
+    to: FspId,
+    /// COMMITTED, RESERVED or ABORTED
+    #[clap(index = 3, default_value = "COMMITTED")]
     state: transfer::TransferState,
-    #[clap(index = 2, required = true, multiple = true)]
-    id: CorrelationId,
+    #[clap(index = 4, required = true, multiple = true)]
+    ids: Vec<transfer::TransferId>,
 }
 
 #[derive(Clap)]
@@ -402,12 +671,16 @@ struct TransferPrepareFulfil {
     from: FspId,
     #[clap(index = 2, required = true)]
     to: FspId,
-    #[clap(index = 3, required = true)]
+    #[clap(index = 3, required = true, parse(try_from_str = currency::parse_currency))]
     currency: Currency,
+    /// COMMITTED, RESERVED or ABORTED
     #[clap(index = 4, default_value = "COMMITTED")]
     state: transfer::TransferState,
-    #[clap(index = 5, required = true, multiple = true)]
-    amount: Amount,
+    #[clap(index = 5, required = true, multiple = true, parse(try_from_str = amount::parse_amount))]
+    amounts: Vec<Amount>,
+    /// Milliseconds to wait between sending prepares and fulfils
+    #[clap(short, long, default_value = "1000")]
+    wait: u64,
 }
 
 #[derive(Clap)]
@@ -420,7 +693,7 @@ struct Hub {
 enum HubSubCommand {
     /// Create and read hub accounts
     Accounts(HubAccounts),
-    /// Create settlement models
+    /// Create and read settlement models
     SettlementModel(SettlementModel),
 }
 
@@ -439,10 +712,22 @@ struct SettlementModel {
 #[derive(Clap)]
 enum SettlementModelSubCommand {
     Create(SettlementModelCreate),
+    /// List settlement models
+    #[clap(alias = "ls")]
+    List,
+    /// Show a settlement model by name
+    Get(SettlementModelGet),
+}
+
+#[derive(Clap)]
+struct SettlementModelGet {
+    #[clap(index = 1, required = true)]
+    name: String,
 }
 
 #[derive(Clap)]
 struct SettlementModelCreate {
+    #[clap(parse(try_from_str = currency::parse_currency))]
     currency: fspiox_api::Currency,
     #[clap(short, long, default_value = "DEFERREDNET")]
     name: mojaloop_api::central_ledger::settlement_models::SettlementModelName,
@@ -491,7 +776,8 @@ enum HubAccountsCreateSubCommand {
 
 #[derive(Clap, Debug)]
 struct HubAccountsCreateOpts {
-    #[clap(index = 1, required = true, multiple = true)]
+    /// May be omitted where a default currency is configured
+    #[clap(index = 1, multiple = true, parse(try_from_str = currency::parse_currency))]
     currencies: Vec<Currency>,
 }
 
@@ -547,12 +833,16 @@ enum ParticipantLimitsSubCommand {
     Get,
     // TODO: is it possible to chain these, i.e. set MMD 10000 set XOF 10000 set EUR 5000 etc.?
     /// Set participant NDC
+    #[clap(setting = clap::AppSettings::AllowMissingPositional)]
     Set(ParticipantLimitsSet),
 }
 
 #[derive(Clap)]
 struct ParticipantLimitsSet {
-    currency: Currency,
+    /// May be omitted where a default currency is configured
+    #[clap(index = 1, parse(try_from_str = currency::parse_currency))]
+    currency: Option<Currency>,
+    #[clap(index = 2, required = true, parse(try_from_str = amount::parse_ndc))]
     value: u32,
 }
 
@@ -585,26 +875,52 @@ struct ParticipantEndpointsSetAll {
     url: url::Url,
 }
 
+// The currency argument of this command is followed by a required argument, so clap can't make it
+// optional. Instead we take the arguments as strings and parse them ourselves; see split_currency.
 #[derive(Clap)]
 struct ParticipantOnboard {
-    /// The currency of the initial account to create for this participant
-    #[clap(required = true)]
+    /// [CURRENCY] URL [NDC] [POSITION]
+    ///
+    /// CURRENCY: the currency of the initial account to create for this participant. May be
+    /// omitted where a default currency is configured.
+    ///
+    /// URL: the host to which all FSPIOP requests destined for this participant will be delivered.
+    ///
+    /// NDC: the net debit cap for the currency account created with this command. Default 0.
+    ///
+    /// POSITION: the initial position of the currency account created with this command. Default
+    /// 0.
+    #[clap(required = true, min_values = 1, max_values = 4, value_name = "ARGS")]
+    args: Vec<String>,
+    /// Where the hub has no reconciliation or multilateral settlement account in the currency,
+    /// create them, along with a DEFERREDNET<CURRENCY> settlement model for the currency
+    #[clap(long)]
+    ensure_hub_accounts: bool,
+}
+
+struct ParticipantOnboardArgs {
     currency: Currency,
-    /// The host to which all FSPIOP requests destined for this participant will be delivered
-    #[clap(required = true)]
     url: url::Url,
-    // TODO: Accept numbers with commas, perhaps scientific notation, perhaps the 1M 1MM etc.
-    // notation. Or 10K 10M 100M etc.
-    /// The net debit cap for the currency account created with this command
-    #[clap(default_value = "0")]
     ndc: u32,
-    // TODO: Accept numbers with commas, perhaps scientific notation, perhaps the 1M 1MM etc.
-    // notation. Or 10K 10M 100M etc.
-    /// The initial position of the currency account created with this command
-    #[clap(default_value = "0")]
     position: Amount,
 }
 
+impl ParticipantOnboard {
+    fn parse(&self, default_currency: Option<Currency>) -> Result<ParticipantOnboardArgs, MojaloopCliError> {
+        let (currency, args) = split_currency(&self.args, default_currency, |a| url::Url::parse(a).is_ok())?;
+        if let Some(unexpected) = args.get(3) {
+            return Err(MojaloopCliError::UnexpectedArgument(unexpected.clone()));
+        }
+        Ok(ParticipantOnboardArgs {
+            currency,
+            url: parse_arg("URL", args.get(0), url::Url::parse)?
+                .ok_or_else(|| MojaloopCliError::MissingArgument("URL".to_string()))?,
+            ndc: parse_arg("NDC", args.get(1), amount::parse_ndc)?.unwrap_or(0),
+            position: parse_arg("POSITION", args.get(2), amount::parse_amount)?.unwrap_or(Amount::ZERO),
+        })
+    }
+}
+
 #[derive(Clap)]
 struct ParticipantAccount {
     #[clap(subcommand)]
@@ -615,7 +931,7 @@ struct ParticipantAccount {
 enum ParticipantAccountsSubCommand {
     /// Fund participant account
     Fund(ParticipantAccountFund),
-    /// Upsert participant account
+    /// Create participant account if it doesn't exist, and set its NDC
     Upsert(ParticipantAccountUpsert),
     // TODO: only prints active accounts, at present- should have a --inactive flag? Or just print
     // is_active status?
@@ -630,21 +946,29 @@ enum ParticipantAccountsSubCommand {
 #[derive(Clap, Debug)]
 struct ParticipantAccountDisable {
     // TODO: accept multiple currencies
-    #[clap(index = 1, required = true, multiple = true)]
+    #[clap(index = 1, required = true, multiple = true, parse(try_from_str = currency::parse_currency))]
     currency: Vec<Currency>,
 }
 
 #[derive(Clap, Debug)]
 struct ParticipantAccountEnable {
     // TODO: accept multiple currencies
-    #[clap(index = 1, required = true, multiple = true)]
+    #[clap(index = 1, required = true, multiple = true, parse(try_from_str = currency::parse_currency))]
     currency: Vec<Currency>,
 }
 
 #[derive(Clap, Debug)]
 struct ParticipantAccountFund {
-    #[clap(index = 1, required = true)]
-    currency: Currency,
+    /// May be omitted where a default currency is configured
+    #[clap(index = 1, parse(try_from_str = currency::parse_currency))]
+    currency: Option<Currency>,
+    /// The reason for the funds movement, recorded in the switch
+    #[clap(short, long, default_value = "Mojaloop CLI request")]
+    reason: String,
+    /// A reference to the funds movement in an external system, for example a bank transfer
+    /// reference, recorded in the switch
+    #[clap(short, long, default_value = "Mojaloop CLI request")]
+    external_reference: String,
     #[clap(subcommand)]
     subcmd: ParticipantAccountFundSubCommand,
 }
@@ -653,8 +977,13 @@ struct ParticipantAccountFund {
 enum ParticipantAccountFundSubCommand {
     /// Process funds into the account.
     In(ParticipantAccountFundsPositive),
-    /// Process funds out of the account.
-    Out(ParticipantAccountFundsPositive),
+    /// Process funds out of the account. The funds are reserved, then the reservation is
+    /// committed, unless --reserve-only is supplied.
+    Out(ParticipantAccountFundsOut),
+    /// Commit a funds out reservation, made with `out --reserve-only`
+    Commit(ParticipantAccountFundsTransfer),
+    /// Abort a funds out reservation, made with `out --reserve-only`
+    Abort(ParticipantAccountFundsTransfer),
     /// Fund a numeric amount. Positive: funds in. Negative: funds out. You'll likely need to
     /// provide the argument after --, thus: participant my_participant fund XOF num -- -100
     Num(ParticipantAccountFunds),
@@ -662,22 +991,47 @@ enum ParticipantAccountFundSubCommand {
 
 #[derive(Clap, Debug)]
 struct ParticipantAccountFunds {
+    /// Must not be zero
+    #[clap(parse(try_from_str = amount::parse_nonzero_amount))]
     amount: Amount,
 }
 
 #[derive(Clap, Debug)]
 struct ParticipantAccountFundsPositive {
-    // TODO: how to enforce this to be positive?
+    /// Must be greater than zero
+    #[clap(parse(try_from_str = amount::parse_amount))]
+    amount: Amount,
+}
+
+#[derive(Clap, Debug)]
+struct ParticipantAccountFundsOut {
+    /// Must be greater than zero
+    #[clap(parse(try_from_str = amount::parse_amount))]
     amount: Amount,
+    /// Only reserve the funds. The transfer ID will be printed, and can be used to commit or
+    /// abort the reservation later.
+    #[clap(long)]
+    reserve_only: bool,
+}
+
+#[derive(Clap, Debug)]
+struct ParticipantAccountFundsTransfer {
+    /// The transfer ID of the funds out reservation
+    transfer_id: CorrelationId,
 }
 
 #[derive(Clap, Debug)]
 struct ParticipantAccountUpsert {
-    #[clap(index = 1)]
-    currency: Currency,
-    #[clap(short, long, requires = "currency")]
-    ndc: Option<Amount>,
-    #[clap(short, long, requires = "currency")]
+    /// The currency of the account to create or update. May be omitted where a default currency
+    /// is configured.
+    #[clap(index = 1, parse(try_from_str = currency::parse_currency))]
+    currency: Option<Currency>,
+    /// The net debit cap for the account. Defaults to zero where the account is created.
+    #[clap(short, long, parse(try_from_str = amount::parse_ndc))]
+    ndc: Option<u32>,
+    /// The initial position of the account. Only used where the account is created. Defaults to
+    /// zero.
+    #[clap(short, long, parse(try_from_str = amount::parse_amount))]
     position: Option<Amount>,
 }
 
@@ -697,7 +1051,7 @@ enum AccountsSubCommand {
 struct AccountsCreate {
     #[clap(index = 1)]
     participant_name: FspId,
-    #[clap(index = 2)]
+    #[clap(index = 2, parse(try_from_str = currency::parse_currency))]
     currency: Currency,
 }
 
@@ -733,81 +1087,406 @@ pub enum MojaloopCliError {
     MojaloopApiError(ErrorResponse),
     #[error("Couldn't load kubeconfig file: {0}")]
     UnableToLoadKubeconfig(String),
-    // TODO: tell the user what command to execute to create such an account?
+    #[error("Kubernetes options (--kubeconfig, --context, --cluster, --user, --namespace) can't be combined with service urls")]
+    ConflictingConnectionOptions,
     #[error("Participant {0} does not have {1} settlement account")]
     ParticipantMissingCurrencyAccount(FspId, Currency),
     #[error("Failed to connect to voodoo doll: {0}")]
     VoodooDollConnectionError(String),
+    #[error("Participant {0} does not exist")]
+    ParticipantNotFound(FspId),
+    #[error("Participant {0} already exists")]
+    ParticipantExists(FspId),
+    #[error("Amount must be greater than zero, received {0}")]
+    NonPositiveAmount(Amount),
+    #[error("--dry-run is not supported for this command")]
+    DryRunUnsupported,
+    #[error("Settlement model {0} does not exist. Available settlement models: {1}")]
+    SettlementModelNotFound(String, String),
+    #[error("Settlement model {0} already exists for currency {1}, so can't be created for {2}. Settlement model names are unique; supply another name.")]
+    SettlementModelConflict(String, Currency, Currency),
+    #[error("No url configured for {0}. Supply it with {1}, or omit all service urls to connect through Kubernetes.")]
+    ServiceUrlNotConfigured(String, String),
+    #[error("Invalid url in {0}: {1}")]
+    InvalidServiceUrl(String, String),
+    #[error("This command requires Kubernetes, and cannot be used with service urls")]
+    KubernetesRequired,
+    #[error("A currency is required. Supply one, or set a default currency with MOJALOOP_CURRENCY or in your profile.")]
+    CurrencyRequired,
+    #[error("Invalid MOJALOOP_CURRENCY: {0}")]
+    InvalidDefaultCurrency(String),
+    #[error("Invalid value for {0}: {1}. {2}")]
+    InvalidArgument(String, String, String),
+    #[error("Missing argument {0}")]
+    MissingArgument(String),
+    #[error("Unexpected argument {0}")]
+    UnexpectedArgument(String),
+    #[error("Couldn't find an open settlement window")]
+    NoOpenSettlementWindow,
+    #[error("Settlement window {0} was not closed within {1} seconds. Supply a longer --timeout, or check the central-settlement logs.")]
+    SettlementWindowCloseTimeout(settlement_windows::SettlementWindowId, u64),
+    #[error("Closing settlement window {0} failed: {1}")]
+    SettlementWindowCloseFailed(settlement_windows::SettlementWindowId, String),
+    #[error("Funds out reservation {0} was not RESERVED within {1} seconds. Commit or abort it later with the commit or abort subcommand.")]
+    FundsOutReservationTimeout(CorrelationId, u64),
+    #[error("Funds out reservation {0} is {1:?}, so can't be committed or aborted")]
+    FundsOutNotReserved(CorrelationId, transfer::TransferState),
+    #[error("At least one filter is required to list settlements")]
+    SettlementFilterRequired,
+    #[error("Settlement accounts cannot be moved to state {0:?}. Valid states are PS_TRANSFERS_RECORDED, PS_TRANSFERS_RESERVED, PS_TRANSFERS_COMMITTED, SETTLED.")]
+    InvalidSettlementAccountState(settlement::SettlementState),
+    #[error("Participant {0} has no accounts in settlement {1}")]
+    ParticipantNotInSettlement(FspId, settlement::SettlementId),
+    #[error("Settlement {0} has been aborted")]
+    SettlementAborted(settlement::SettlementId),
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    use mojaloop_api::clients::FspiopClient;
-    let opts: Opts = Opts::parse();
+/// The currency used where a currency argument is omitted: MOJALOOP_CURRENCY, or, failing that, the
+/// currency of the profile
+fn default_currency(profile: &profile::Profile) -> Result<Option<Currency>, MojaloopCliError> {
+    match std::env::var("MOJALOOP_CURRENCY") {
+        Ok(value) => currency::parse_currency(&value)
+            .map(Some)
+            .map_err(|e| MojaloopCliError::InvalidDefaultCurrency(e.to_string())),
+        Err(_) => Ok(profile.currency),
+    }
+}
+
+fn currency_or_default(supplied: Option<Currency>, default: Option<Currency>) -> Result<Currency, MojaloopCliError> {
+    supplied.or(default).ok_or(MojaloopCliError::CurrencyRequired)
+}
+
+/// Where the first argument is a currency, return it and the remaining arguments. Where the first
+/// argument is instead the argument expected to follow the currency, as determined by `next`,
+/// return the default currency, and all the arguments. Otherwise the first argument was intended
+/// as a currency, so report why it isn't one.
+fn split_currency<F>(args: &[String], default: Option<Currency>, next: F) -> Result<(Currency, &[String]), MojaloopCliError>
+where
+    F: Fn(&str) -> bool,
+{
+    let first = match args.first() {
+        Some(first) => first,
+        None => return default.map(|currency| (currency, args)).ok_or(MojaloopCliError::CurrencyRequired),
+    };
+    match currency::parse_currency(first) {
+        Ok(currency) => Ok((currency, &args[1..])),
+        Err(_) if next(first) => default.map(|currency| (currency, args)).ok_or(MojaloopCliError::CurrencyRequired),
+        Err(e) => Err(MojaloopCliError::InvalidArgument("CURRENCY".to_string(), first.clone(), e.to_string())),
+    }
+}
+
+fn parse_arg<T, E, F>(name: &str, value: Option<&String>, parse: F) -> Result<Option<T>, MojaloopCliError>
+where
+    F: Fn(&str) -> Result<T, E>,
+    E: std::fmt::Display,
+{
+    value
+        .map(|v| parse(v).map_err(|e| MojaloopCliError::InvalidArgument(name.to_string(), v.clone(), e.to_string())))
+        .transpose()
+}
+
+/// The endpoint configured for the given participant and callback type, if any
+async fn callback_url(
+    clients: &mut Clients,
+    participant: &FspId,
+    callback_type: FspiopCallbackType,
+) -> anyhow::Result<Option<String>> {
+    let request = GetCallbackUrls { name: participant.clone() };
+    let endpoints = clients.central_ledger().await?.send(request).await?.des().await?;
+    Ok(endpoints.into_iter().find(|ep| ep.r#type == callback_type).map(|ep| ep.value))
+}
+
+/// The operations required for participants to hold accounts in the given currency: hub
+/// reconciliation and multilateral settlement accounts, and a settlement model. Anything that
+/// already exists is omitted.
+async fn hub_currency_operations(
+    clients: &mut Clients,
+    currency: Currency,
+) -> anyhow::Result<Vec<operations::Operation>> {
+    let config = switch_config::SwitchConfig {
+        hub: switch_config::HubConfig { currencies: vec![currency] },
+        settlement_models: vec![switch_config::SettlementModelConfig::deferred_net(currency)?],
+        participants: Vec::new(),
+    };
+    apply::plan(&config, clients).await
+}
+
+/// Wait for a settlement window to be closed. A window being closed is PROCESSING until a
+/// central-settlement handler closes it.
+async fn wait_for_window_close(
+    clients: &mut Clients,
+    id: settlement_windows::SettlementWindowId,
+    timeout: std::time::Duration,
+) -> anyhow::Result<()> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let request = settlement_windows::GetSettlementWindow { id };
+        let window = clients.settlement().await?.send(request).await?.des().await?;
+        match window.state {
+            settlement_windows::SettlementWindowState::Closed => return Ok(()),
+            settlement_windows::SettlementWindowState::Aborted =>
+                return Err(MojaloopCliError::SettlementWindowCloseFailed(id, window.reason.unwrap_or_default()).into()),
+            _ if tokio::time::Instant::now() >= deadline =>
+                return Err(MojaloopCliError::SettlementWindowCloseTimeout(id, timeout.as_secs()).into()),
+            _ => tokio::time::sleep(std::time::Duration::from_millis(500)).await,
+        }
+    }
+}
+
+/// Find a settlement model by name. Where it doesn't exist, the error lists the models that do,
+/// as the switch only tells us "Settlement model not found".
+async fn find_settlement_model(
+    clients: &mut Clients,
+    name: &str,
+) -> anyhow::Result<settlement_models::SettlementModel> {
+    let models = clients.central_ledger().await?
+        .send(settlement_models::GetSettlementModels {}).await?.des().await?;
+    let available = if models.is_empty() {
+        "none. Create one with `mojaloop-cli hub settlement-model create`".to_string()
+    } else {
+        models.iter().map(|m| format!("{} ({})", m.name, m.currency)).collect::<Vec<_>>().join(", ")
+    };
+    models.into_iter()
+        .find(|m| m.name.to_string() == name)
+        .ok_or_else(|| MojaloopCliError::SettlementModelNotFound(name.to_string(), available).into())
+}
+
+/// Perform the supplied operations and report them to the user. In human mode, operations are
+/// reported as they're performed.
+async fn perform(
+    operations: Vec<operations::Operation>,
+    clients: &mut Clients,
+    dry_run: bool,
+    strict: bool,
+    output: &Output,
+) -> anyhow::Result<operations::Report> {
+    let report = operations::perform(operations, clients, dry_run, strict, output).await?;
+    output.emit(&report, |r| {
+        if r.operations.is_empty() {
+            println!("No changes required");
+        }
+        Ok(())
+    })?;
+    Ok(report)
+}
 
-    let client = match opts.kubeconfig {
+/// Create a Kubernetes client from the supplied kubeconfig file, or the default kubeconfig where
+/// none is supplied. Where no file or options are supplied, this falls back to the in-cluster
+/// configuration if there's no kubeconfig, as kubectl does.
+async fn kube_client(
+    kubeconfig: Option<&std::path::Path>,
+    options: &kube::config::KubeConfigOptions,
+) -> Result<Client, MojaloopCliError> {
+    let no_options = options.context.is_none() && options.cluster.is_none() && options.user.is_none();
+    let config = match kubeconfig {
         Some(path) => {
-            let custom_config = kube::config::Kubeconfig::read_from(path.as_path())
-                .map_err(|e| MojaloopCliError::UnableToLoadKubeconfig(e.to_string()))?;
-            // TODO: expose some of this to the user?
-            let options = kube::config::KubeConfigOptions {
-                context: None,
-                cluster: None,
-                user: None,
-            };
-            let config = kube::Config::from_custom_kubeconfig(custom_config, &options).await
+            let custom_config = kube::config::Kubeconfig::read_from(path)
                 .map_err(|e| MojaloopCliError::UnableToLoadKubeconfig(e.to_string()))?;
-            Client::try_from(config)
-                .map_err(|e| MojaloopCliError::UnableToLoadKubeconfig(e.to_string()))?
+            kube::Config::from_custom_kubeconfig(custom_config, options).await
         },
-        None => Client::try_default().await
-            .map_err(|e| MojaloopCliError::UnableToLoadKubeconfig(e.to_string()))?
-    };
+        None if no_options => return Client::try_default().await
+            .map_err(|e| MojaloopCliError::UnableToLoadKubeconfig(e.to_string())),
+        None => kube::Config::from_kubeconfig(options).await,
+    }.map_err(|e| MojaloopCliError::UnableToLoadKubeconfig(e.to_string()))?;
+    Client::try_from(config)
+        .map_err(|e| MojaloopCliError::UnableToLoadKubeconfig(e.to_string()))
+}
 
-    async fn set_participant_endpoints(
-        participant_name: &FspId,
-        url: &String,
-        client: &mut mojaloop_api::clients::central_ledger::Client,
-    ) -> anyhow::Result<()> {
-        // TODO: strip trailing slash
-        for callback_type in FspiopCallbackType::iter() {
-            let request = PostCallbackUrl {
-                name: participant_name.clone(),
-                callback_type,
-                // TODO: strip trailing slash
-                hostname: url.clone(),
-            };
-            client.send(request).await?;
-            // TODO: url.clone() is just the hostname the user provided, not the actual endpoint
-            // template. This could be confusing. We should show the whole endpoint template.
-            println!("Updated {:?} endpoint to {}", callback_type, url.clone());
+impl Opts {
+    /// Take any options not supplied on the command line from the profile
+    fn apply_profile(&mut self, profile: &profile::Profile) {
+        self.hub_name = self.hub_name.take().or_else(|| profile.hub_name.clone());
+        self.json = self.json || profile.output == Some(profile::OutputFormat::Json);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut opts: Opts = match Opts::try_parse() {
+        Ok(opts) => opts,
+        // Help and version output aren't errors, and go to stdout as usual
+        Err(e) if e.use_stderr() && json_requested() => {
+            Output::new(true).error(&e.into());
+            std::process::exit(2);
         }
-        Ok(())
+        Err(e) => e.exit(),
+    };
+    let profile_name = opts.profile.clone().or_else(|| std::env::var("MOJALOOP_PROFILE").ok());
+    let profile = match profile::Config::load().and_then(|c| c.profile(profile_name.as_deref())) {
+        Ok(profile) => profile,
+        Err(e) => {
+            Output::new(opts.json).error(&e.into());
+            std::process::exit(1);
+        }
+    };
+    opts.apply_profile(&profile);
+    let output = Output::new(opts.json);
+
+    if let Err(e) = run(opts, profile.clone(), output).await {
+        output.error(&e);
+        match hints::hint(&e) {
+            Some(hint) if hint.runnable() && !output.is_json() && prompt::interactive() && !prompt::asked() => {
+                if prompt::confirm("Run this command now?").unwrap_or(false) {
+                    match run_hint(&hint, profile, output).await {
+                        Ok(()) => eprintln!("Done. Re-run your original command."),
+                        Err(e) => output.error(&e),
+                    }
+                }
+            },
+            _ => {},
+        }
+        std::process::exit(1);
     }
+}
+
+/// Whether --json was supplied. Used where the arguments couldn't be parsed, so that even usage
+/// errors are reported as a JSON document.
+fn json_requested() -> bool {
+    std::env::args().skip(1).take_while(|a| a != "--").any(|a| a == "--json" || a == "-j")
+}
+
+/// A subcommand alone, without global options. Used to parse the command suggested by a hint.
+#[derive(Clap)]
+struct HintCommand {
+    #[clap(subcommand)]
+    subcmd: SubCommand,
+}
+
+/// Run the command suggested by a hint, with the global options of this invocation
+async fn run_hint(hint: &hints::Hint, profile: profile::Profile, output: Output) -> anyhow::Result<()> {
+    let args = hint.command.iter().flatten().map(|a| a.as_str());
+    let command = HintCommand::try_parse_from(std::iter::once("mojaloop-cli").chain(args))?;
+    let mut opts = Opts::parse();
+    opts.apply_profile(&profile);
+    opts.subcmd = command.subcmd;
+    run(opts, profile, output).await
+}
 
-    // TODO: collect a list of actions to take, then pass them to a function that takes those
-    // actions. This will make a --dry-run option easier. It will also make a declarative format
-    // (i.e. "I want this config") easier. Operations could look like this:
-    // enum Operations {
-    //   CreateHubMultilateralSettlementAccount({ currency: Currency }),
-    //   CreateParticipantAccount({ currency: Currency }),
-    // }
-    // This would serialise fairly easily to something presentable to a user, and consumable by
-    // code. It could get complicated if the output of one command guided subsequent commands. But
-    // perhaps it's better to let the user make decisions? Alternatively, we _could_ have a tree of
-    // actions and encode inputs and outputs. Getting pretty complex now, maybe not the best idea.
-    // This is what a programming language is for. This tool should instead focus on simplicity.
-    // The Operations and corresponding functionality could be exported to be used elsewhere.
-    // let operations = match opts.subcmd {
+async fn run(opts: Opts, profile: profile::Profile, output: Output) -> anyhow::Result<()> {
+
+    // TODO: if we implement pools in fspiox_api with a minimum connection count of zero, we could
+    // "get" all clients at once, and lazily connect to them. This would make getting clients much
+    // more elegant. For now, clients::Clients creates each client on first use.
+    //
+    // Kubernetes options supplied on the command line select Kubernetes mode, even where service
+    // urls are set in the environment or the profile. Otherwise we use service urls from the
+    // command line, the environment or the profile, in that order, and only where there are none
+    // do we fall back to Kubernetes, with options from the profile.
+    let kube_args = opts.kubeconfig.is_some() || opts.context.is_some() || opts.cluster.is_some()
+        || opts.user.is_some() || opts.namespace.is_some();
+    let url_args = clients::ServiceUrls {
+        central_ledger: opts.central_ledger_url.clone(),
+        settlement: opts.settlement_url.clone(),
+        quote: opts.quote_url.clone(),
+        transfer: opts.transfer_url.clone(),
+    };
+    if kube_args && !url_args.is_empty() {
+        return Err(MojaloopCliError::ConflictingConnectionOptions.into());
+    }
+    let service_urls = if kube_args {
+        url_args
+    } else {
+        url_args.or_env()?.or(clients::ServiceUrls {
+            central_ledger: profile.central_ledger_url.clone(),
+            settlement: profile.settlement_url.clone(),
+            quote: profile.quote_url.clone(),
+            transfer: profile.transfer_url.clone(),
+        })
+    };
+    let hub_name = opts.hub_name.clone().unwrap_or_else(|| FspId::from("Hub").unwrap());
+    let default_currency = default_currency(&profile)?;
+    let mut clients = if service_urls.is_empty() {
+        let options = kube::config::KubeConfigOptions {
+            context: opts.context.clone().or_else(|| profile.context.clone()),
+            cluster: opts.cluster.clone().or_else(|| profile.cluster.clone()),
+            user: opts.user.clone().or_else(|| profile.user.clone()),
+        };
+        let kubeconfig = opts.kubeconfig.clone().or_else(|| profile.kubeconfig.clone());
+        let client = kube_client(kubeconfig.as_deref(), &options).await?;
+        Clients::new(client, opts.namespace.clone().or_else(|| profile.namespace.clone()))
+    } else {
+        Clients::direct(service_urls)
+    }.with_hub(hub_name);
+
+    // Commands that modify the switch assess what needs to be done and produce a list of
+    // operations, then pass them to `perform`, which either executes them or, in --dry-run mode,
+    // prints them. See operations.rs.
     match opts.subcmd {
+        SubCommand::Apply(apply_args) => {
+            let config = switch_config::SwitchConfig::from_file(&apply_args.file)?;
+            let operations = apply::plan(&config, &mut clients).await?;
+            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
+        }
+
+        SubCommand::QuickStart(quick_start_args) => {
+            let currencies = if quick_start_args.currency.is_empty() {
+                vec![currency_or_default(None, default_currency)?]
+            } else {
+                quick_start_args.currency.clone()
+            };
+            for currency in &currencies {
+                amount::check_precision(quick_start_args.position, *currency)?;
+            }
+            let participants = quick_start_args.participant.iter()
+                .map(|name| {
+                    let endpoint = quick_start_args.endpoint.replace("{fsp}", &name.to_string());
+                    let endpoint = url::Url::parse(&endpoint).map_err(|e| MojaloopCliError::InvalidArgument(
+                        "--endpoint".to_string(), endpoint.clone(), e.to_string()
+                    ))?;
+                    Ok(switch_config::ParticipantConfig {
+                        name: name.clone(),
+                        endpoint: Some(endpoint),
+                        accounts: currencies.iter()
+                            .map(|currency| switch_config::ParticipantAccountConfig {
+                                currency: *currency,
+                                ndc: quick_start_args.ndc,
+                                position: quick_start_args.position,
+                            })
+                            .collect(),
+                    })
+                })
+                .collect::<Result<Vec<_>, MojaloopCliError>>()?;
+            let config = switch_config::SwitchConfig {
+                hub: switch_config::HubConfig { currencies: currencies.clone() },
+                settlement_models: currencies.iter()
+                    .map(|currency| switch_config::SettlementModelConfig::deferred_net(*currency))
+                    .collect::<Result<Vec<_>, _>>()?,
+                participants,
+            };
+            let operations = apply::plan_missing(&config, &mut clients).await?;
+            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
+        }
+
+        SubCommand::Diff(diff_args) => {
+            let config = switch_config::SwitchConfig::from_file(&diff_args.file)?;
+            let differences = diff::diff(&config, &mut clients).await?;
+            output.emit(&output::Differences { differences: &differences }, |d| {
+                if d.differences.is_empty() {
+                    println!("No differences");
+                }
+                for difference in d.differences {
+                    println!("{}", difference);
+                }
+                Ok(())
+            })?;
+            if diff_args.exit_code && !differences.is_empty() {
+                std::process::exit(1);
+            }
+        }
+
+        SubCommand::Export(export_args) => {
+            let config = export::export(&mut clients, &output).await?;
+            match export_args.file {
+                Some(path) => {
+                    let json = opts.json || path.extension().and_then(|ext| ext.to_str()) == Some("json");
+                    std::fs::write(&path, config.serialise(json)?)?;
+                    output.info(format!("Wrote switch configuration to {}", path.display()));
+                }
+                None => print!("{}", config.serialise(opts.json)?),
+            }
+        }
+
         SubCommand::Settlement(settlement_args) => {
-            // TODO: if we implement pools in fspiox_api with a minimum connection count of zero,
-            // we could "get" all clients at once, and lazily connect to them. This would make
-            // getting clients much more elegant.
-            let mut ml_settlement = mojaloop_api::clients::settlement::Client::from_k8s_params(
-                Some(client),
-                &opts.namespace,
-            ).await?;
             match settlement_args.subcmd {
                 SettlementSubCommand::Window(window_args) => {
                     match window_args.subcmd {
@@ -816,81 +1495,325 @@ async fn main() -> anyhow::Result<()> {
                                 id: get_window_args.id
                             };
 
-                            let window = ml_settlement.send(request).await?.des().await?;
-                            println!("{:?}", window);
+                            let window = clients.settlement().await?.send(request).await?.des().await?;
+                            output.emit(&window, settlements::print_settlement_window)?;
                         }
 
                         SettlementWindowSubCommand::Filter(filter_window_args) => {
-                            let request = settlement_windows::GetSettlementWindows {
-                                state: Some(filter_window_args.state),
-                                currency: None,
-                                from_date_time: None,
-                                participant_id: None,
-                                to_date_time: None,
+                            let no_filters =
+                                filter_window_args.state.is_empty() &&
+                                filter_window_args.currency.is_none() &&
+                                filter_window_args.participant_id.is_none() &&
+                                filter_window_args.from.is_none() &&
+                                filter_window_args.to.is_none();
+                            // The settlement service accepts only a single state per request, so
+                            // we make a request per state. None means "any state".
+                            let states: Vec<Option<settlement_windows::SettlementWindowState>> = if no_filters {
+                                vec![Some(settlement_windows::SettlementWindowState::Open)]
+                            } else if filter_window_args.state.is_empty() {
+                                vec![None]
+                            } else {
+                                // A state supplied more than once would otherwise list its windows
+                                // more than once
+                                let mut states = Vec::new();
+                                for state in filter_window_args.state.iter().map(|s| Some(*s)) {
+                                    if !states.contains(&state) {
+                                        states.push(state);
+                                    }
+                                }
+                                states
                             };
 
-                            let windows = ml_settlement.send(request).await?;
+                            let mut windows = Vec::new();
+                            for state in states {
+                                let request = settlement_windows::GetSettlementWindows {
+                                    state,
+                                    currency: filter_window_args.currency,
+                                    from_date_time: filter_window_args.from,
+                                    participant_id: filter_window_args.participant_id,
+                                    to_date_time: filter_window_args.to,
+                                };
+                                windows.extend(clients.settlement().await?.send(request).await?.des().await?);
+                            }
 
-                            // TODO: table
-                            println!("{:?}", windows);
+                            output.emit(&windows, |ws| {
+                                let table = ws.iter()
+                                    .map(|w| vec![
+                                        w.settlement_window_id.cell(),
+                                        format!("{:?}", w.state).cell(),
+                                        w.content.iter().flatten()
+                                            .map(|c| format!("{} {:?}", c.currency_id, c.ledger_account_type))
+                                            .collect::<Vec<_>>()
+                                            .join(", ")
+                                            .cell(),
+                                        w.reason.as_deref().unwrap_or("").cell(),
+                                        w.created_date.cell(),
+                                        w.changed_date.map_or_else(String::new, |d| d.to_string()).cell(),
+                                    ])
+                                    .table()
+                                    .title(vec![
+                                        "ID".cell(),
+                                        "State".cell(),
+                                        "Content".cell(),
+                                        "Reason".cell(),
+                                        "Created date".cell(),
+                                        "Changed date".cell(),
+                                    ]);
+                                print_stdout(table)?;
+                                Ok(())
+                            })?;
                         }
 
                         SettlementWindowSubCommand::Close(close_window_args) => {
-                            let request = settlement_windows::CloseSettlementWindow {
-                                id: close_window_args.id,
-                                payload: settlement_windows::SettlementWindowClosurePayload {
+                            let operations = vec![
+                                operations::Operation::CloseSettlementWindow {
+                                    id: close_window_args.id,
                                     reason: close_window_args.reason,
-                                    state: settlement_windows::SettlementWindowCloseState::Closed,
-                                }
-                            };
+                                },
+                            ];
+                            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
+                        }
+                    }
+                }
 
-                            ml_settlement.send(request).await?;
+                SettlementSubCommand::Get(get_settlement_args) => {
+                    let request = settlement::GetSettlement { id: get_settlement_args.id };
+                    let s = clients.settlement().await?.send(request).await?.des().await?;
+                    let names = if output.is_json() {
+                        settlements::ParticipantNames::default()
+                    } else {
+                        settlements::ParticipantNames::fetch(&mut clients).await?
+                    };
+                    output.emit(&s, |s| settlements::print_settlement(s, &names))?;
+                }
 
-                            println!("Closed window: {}", close_window_args.id);
+                SettlementSubCommand::List(list_settlement_args) => {
+                    let no_filters =
+                        list_settlement_args.state.is_none() &&
+                        list_settlement_args.currency.is_none() &&
+                        list_settlement_args.participant_id.is_none() &&
+                        list_settlement_args.from.is_none() &&
+                        list_settlement_args.to.is_none();
+                    if no_filters {
+                        return Err(MojaloopCliError::SettlementFilterRequired.into());
+                    }
+                    let request = settlement::GetSettlements {
+                        state: list_settlement_args.state,
+                        currency: list_settlement_args.currency,
+                        participant_id: list_settlement_args.participant_id,
+                        from_date_time: list_settlement_args.from,
+                        to_date_time: list_settlement_args.to,
+                        account_id: None,
+                        settlement_window_id: None,
+                        from_settlement_window_date_time: None,
+                        to_settlement_window_date_time: None,
+                    };
+                    let settlements = clients.settlement().await?.send(request).await?.des().await?;
+                    output.emit(&settlements, |settlements| {
+                        let table = settlements.iter()
+                            .map(|s| vec![
+                                s.id.cell(),
+                                format!("{:?}", s.state).cell(),
+                                s.settlement_windows.iter()
+                                    .map(|w| w.id.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                                    .cell(),
+                                s.participants.len().cell(),
+                                s.created_date.cell(),
+                                s.changed_date.cell(),
+                            ])
+                            .table()
+                            .title(vec![
+                                "ID".cell(),
+                                "State".cell(),
+                                "Windows".cell(),
+                                "Participants".cell(),
+                                "Created date".cell(),
+                                "Changed date".cell(),
+                            ]);
+                        print_stdout(table)?;
+                        Ok(())
+                    })?;
+                }
+
+                SettlementSubCommand::Report(report_args) => {
+                    let request = settlement::GetSettlement { id: report_args.id };
+                    let s = clients.settlement().await?.send(request).await?.des().await?;
+                    let names = settlements::ParticipantNames::fetch(&mut clients).await?;
+                    let report = settlements::Report::new(&s, &names);
+                    let format = if opts.json { settlements::ReportFormat::Json } else { report_args.format };
+                    let rendered = report.render(format)?;
+                    match report_args.output {
+                        Some(path) => {
+                            std::fs::write(&path, rendered)?;
+                            output.info(format!("Wrote settlement report to {}", path.display()));
+                        }
+                        None => print!("{}", rendered),
+                    }
+                }
+
+                SettlementSubCommand::Advance(advance_settlement_args) => {
+                    let request = settlement::GetSettlement { id: advance_settlement_args.id };
+                    let current = clients.settlement().await?.send(request).await?.des().await?;
+                    let names = if advance_settlement_args.participant.is_empty() {
+                        None
+                    } else {
+                        Some(settlements::ParticipantNames::fetch(&mut clients).await?)
+                    };
+                    let operations = settlements::advance(
+                        &current,
+                        advance_settlement_args.state,
+                        names.as_ref().map(|n| (&advance_settlement_args.participant[..], n)),
+                        &advance_settlement_args.reason,
+                        &advance_settlement_args.external_reference,
+                    )?;
+                    let report = perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
+                    if !opts.dry_run {
+                        if let Some(operations::Outcome::Settlement(s)) = report.operations.last().map(|e| &e.result) {
+                            output.info(format!("Settlement {} is {:?}", s.id, s.state));
                         }
                     }
                 }
 
                 SettlementSubCommand::Create(create_settlement_args) => {
-                    let request = settlement::PostSettlement {
-                        new_settlement: settlement::NewSettlement {
-                            reason: create_settlement_args.reason,
+                    find_settlement_model(&mut clients, &create_settlement_args.settlement_model).await?;
+                    let operations = vec![
+                        operations::Operation::CreateSettlement {
                             settlement_model: create_settlement_args.settlement_model,
-                            settlement_windows: create_settlement_args.settlement_window_ids
-                                .iter()
-                                .map(|id| settlement::WindowParametersNewSettlement { id: *id })
-                                .collect(),
-                        }
+                            reason: create_settlement_args.reason,
+                            settlement_windows: create_settlement_args.settlement_window_ids,
+                        },
+                    ];
+                    let report = operations::perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
+
+                    let names = if output.is_json() || opts.dry_run {
+                        settlements::ParticipantNames::default()
+                    } else {
+                        settlements::ParticipantNames::fetch(&mut clients).await?
                     };
-                    let new_settlement = ml_settlement.send(request).await?.des().await?;
-
-                    // TODO: handle this response:
-                    // {
-                    //   "errorInformation": {
-                    //     "errorCode": "3100",
-                    //     "errorDescription": "Generic validation error - Settlement model not found"
-                    //   }
-                    // }
-                    // by getting available settlement models and listing them for the user
-
-                    // TODO: pretty-print
-                    println!("Created settlement ID: {:?}. Result: {:?}", new_settlement.id, new_settlement);
+                    output.emit(&report, |r| {
+                        for executed in &r.operations {
+                            if let operations::Outcome::Settlement(s) = &executed.result {
+                                println!();
+                                settlements::print_settlement(s, &names)?;
+                            }
+                        }
+                        Ok(())
+                    })?;
                 }
             }
         }
 
+        SubCommand::Settle(settle_args) => {
+            let request = settlement_windows::GetSettlementWindows {
+                state: Some(settlement_windows::SettlementWindowState::Open),
+                currency: None,
+                from_date_time: None,
+                participant_id: None,
+                to_date_time: None,
+            };
+            let open_window = clients.settlement().await?.send(request).await?.des().await?
+                .into_iter()
+                .next()
+                .ok_or(MojaloopCliError::NoOpenSettlementWindow)?;
+            find_settlement_model(&mut clients, &settle_args.settlement_model).await?;
+
+            let window_id = open_window.settlement_window_id;
+            let operations = vec![
+                operations::Operation::CloseSettlementWindow {
+                    id: window_id,
+                    reason: settle_args.reason.clone(),
+                },
+            ];
+            let mut report = operations::perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
+
+            // Central-settlement closes windows asynchronously, and a settlement can only be
+            // created from closed windows
+            if opts.dry_run {
+                output.info(format!("Would: Wait for settlement window {} to close", window_id));
+            } else {
+                let timeout = std::time::Duration::from_secs(opts.timeout.unwrap_or(30).into());
+                wait_for_window_close(&mut clients, window_id, timeout).await?;
+            }
+
+            let operations = vec![
+                operations::Operation::CreateSettlement {
+                    settlement_model: settle_args.settlement_model,
+                    reason: settle_args.reason.clone(),
+                    settlement_windows: vec![window_id],
+                },
+            ];
+            let created = operations::perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
+            report.operations.extend(created.operations);
+            report.skipped.extend(created.skipped);
+
+            // The accounts to be settled are only known once the settlement has been created
+            let net_positions = if opts.dry_run {
+                output.info("Would: Set all accounts of the new settlement to Settled");
+                Vec::new()
+            } else {
+                let operations = match report.operations.last().map(|e| &e.result) {
+                    Some(operations::Outcome::Settlement(s)) => settlements::advance(
+                        s,
+                        settlement::SettlementState::Settled,
+                        None,
+                        &settle_args.reason,
+                        &settle_args.external_reference,
+                    )?,
+                    _ => Vec::new(),
+                };
+                let advanced = operations::perform(operations, &mut clients, false, opts.strict, &output).await?;
+                report.operations.extend(advanced.operations);
+                report.skipped.extend(advanced.skipped);
+                let names = settlements::ParticipantNames::fetch(&mut clients).await?;
+                report.operations.iter().rev()
+                    .find_map(|e| match &e.result {
+                        operations::Outcome::Settlement(s) => Some(settlements::net_positions(s, &names)),
+                        _ => None,
+                    })
+                    .unwrap_or_default()
+            };
+
+            output.emit(&output::Settled { report, net_positions }, |s| {
+                if !s.net_positions.is_empty() {
+                    settlements::print_net_positions(&s.net_positions)?;
+                }
+                Ok(())
+            })?;
+        }
+
         SubCommand::Quote(quote_args) => {
-            let mut ml_quote = mojaloop_api::clients::quote::Client::from_k8s_params(
-                Some(client),
-                &opts.namespace,
-            ).await?;
+            if opts.dry_run {
+                return Err(MojaloopCliError::DryRunUnsupported.into());
+            }
             match quote_args.subcmd {
+                QuoteSubCommand::Get(quote_get_args) => {
+                    let request = quote::QuoteGetRequest::new(quote_get_args.fsp, quote_get_args.id);
+                    clients.quote().await?.send(request).await?;
+                    let result = output::LookupSent {
+                        fsp: quote_get_args.fsp,
+                        resource: format!("/quotes/{}", quote_get_args.id),
+                        callback_url: callback_url(
+                            &mut clients,
+                            &quote_get_args.fsp,
+                            FspiopCallbackType::FspiopCallbackUrlQuotes,
+                        ).await?,
+                    };
+                    output.emit(&result, |r| {
+                        println!("{}", r);
+                        Ok(())
+                    })?;
+                }
+
                 QuoteSubCommand::Create(quote_create_args) => {
+                    let currency = currency_or_default(quote_create_args.currency, default_currency)?;
+                    amount::check_precision(quote_create_args.amount, currency)?;
                     let post_quote = quote::QuoteRequest::new(
                         quote_create_args.from,
                         quote_create_args.to,
                         quote_create_args.amount,
-                        quote_create_args.currency,
+                        currency,
                     );
 
                     // TODO: what is this weird pattern? Is it necessary?
@@ -900,40 +1823,54 @@ async fn main() -> anyhow::Result<()> {
                         panic!();
                     };
 
-                    ml_quote.send(post_quote).await?;
-                    println!("{{ \"quote_id\": \"{}\", \"transaction_id\": \"{}\" }}", quote_id, transaction_id);
+                    clients.quote().await?.send(post_quote).await?;
+                    let result = output::QuoteCreated {
+                        quote_id: quote_id.to_string(),
+                        transaction_id: transaction_id.to_string(),
+                    };
+                    // Human output has always been JSON for this command, so it's the same in
+                    // both modes
+                    println!("{}", serde_json::to_string(&result)?);
                 }
             }
         }
 
         SubCommand::Transfer(transfer_args) => {
-            let mut ml_transfer = mojaloop_api::clients::transfer::Client::from_k8s_params(
-                Some(client),
-                &opts.namespace,
-            ).await?;
+            if opts.dry_run {
+                return Err(MojaloopCliError::DryRunUnsupported.into());
+            }
             match transfer_args.subcmd {
                 TransferSubCommand::Prepare(transfer_prepare_args) => {
                     match transfer_prepare_args.subcmd {
                         TransferPrepareSubCommand::New(transfer_prepare_new_args) => {
                             let transfer_id = transfer::TransferId(CorrelationId::new());
+                            let currency = currency_or_default(transfer_prepare_new_args.currency, default_currency)?;
+                            amount::check_precision(transfer_prepare_new_args.amount, currency)?;
 
                             let transfer_prepare = transfer::TransferPrepareRequest::new(
                                 transfer_prepare_new_args.from,
                                 transfer_prepare_new_args.to,
                                 transfer_prepare_new_args.amount,
-                                transfer_prepare_new_args.currency,
+                                currency,
                                 Some(transfer_id),
                             );
 
-                            println!("Sending {:?}", transfer_prepare);
+                            output.info(format!("Sending {:?}", transfer_prepare));
 
-                            ml_transfer.send(transfer_prepare).await?;
+                            clients.transfer().await?.send(transfer_prepare).await?;
 
-                            println!("{}", transfer_id);
+                            output.emit(&output::TransferPrepared { transfer_id }, |t| {
+                                println!("{}", t.transfer_id);
+                                Ok(())
+                            })?;
                         },
 
                         TransferPrepareSubCommand::FromTransaction(transfer_prepare_from_transaction_args) => {
                             // TODO: dedupe this with the above, if possible
+                            amount::check_precision(
+                                transfer_prepare_from_transaction_args.amount,
+                                transfer_prepare_from_transaction_args.currency,
+                            )?;
                             let transfer_prepare = transfer::TransferPrepareRequest::new(
                                 transfer_prepare_from_transaction_args.from,
                                 transfer_prepare_from_transaction_args.to,
@@ -949,40 +1886,201 @@ async fn main() -> anyhow::Result<()> {
                                 panic!();
                             };
 
-                            ml_transfer.send(transfer_prepare).await?;
+                            clients.transfer().await?.send(transfer_prepare).await?;
 
-                            println!("{}", transfer_id);
+                            output.emit(&output::TransferPrepared { transfer_id }, |t| {
+                                println!("{}", t.transfer_id);
+                                Ok(())
+                            })?;
+                        },
+                    }
+                }
+
+                TransferSubCommand::Get(transfer_get_args) => {
+                    let request = transfer::TransferGetRequest::new(transfer_get_args.fsp, transfer_get_args.id);
+                    clients.transfer().await?.send(request).await?;
+                    let state = if transfer_get_args.admin {
+                        let request = central_ledger_transfers::GetTransfer { id: transfer_get_args.id };
+                        let transfer = clients.central_ledger().await?.send(request).await?.des().await?;
+                        Some(transfer.transfer_state)
+                    } else {
+                        None
+                    };
+                    let result = output::TransferLookup {
+                        lookup: output::LookupSent {
+                            fsp: transfer_get_args.fsp,
+                            resource: format!("/transfers/{}", transfer_get_args.id),
+                            callback_url: callback_url(
+                                &mut clients,
+                                &transfer_get_args.fsp,
+                                FspiopCallbackType::FspiopCallbackUrlTransferPut,
+                            ).await?,
                         },
+                        state,
+                    };
+                    output.emit(&result, |r| {
+                        println!("{}", r.lookup);
+                        if let Some(state) = &r.state {
+                            println!("State: {:?}", state);
+                        }
+                        Ok(())
+                    })?;
+                }
+
+                TransferSubCommand::Fulfil(transfer_fulfil_args) => {
+                    let mut fulfilled = Vec::new();
+                    for transfer_id in &transfer_fulfil_args.ids {
+                        let transfer_fulfil = transfer::TransferFulfilRequest::new(
+                            transfer_fulfil_args.from,
+                            transfer_fulfil_args.to,
+                            transfer_fulfil_args.state,
+                            *transfer_id,
+                        );
+                        clients.transfer().await?.send(transfer_fulfil).await?;
+                        fulfilled.push(output::TransferFulfilled {
+                            transfer_id: *transfer_id,
+                            state: transfer_fulfil_args.state,
+                        });
                     }
+                    output.emit(&fulfilled, |fulfilled| {
+                        for f in fulfilled {
+                            println!("{} {:?}", f.transfer_id, f.state);
+                        }
+                        Ok(())
+                    })?;
+                }
+
+                TransferSubCommand::PrepareFulfil(transfer_prepare_fulfil_args) => {
+                    let mut transfer_ids = Vec::new();
+                    for amount in &transfer_prepare_fulfil_args.amounts {
+                        amount::check_precision(*amount, transfer_prepare_fulfil_args.currency)?;
+                    }
+                    for amount in &transfer_prepare_fulfil_args.amounts {
+                        let transfer_id = transfer::TransferId(CorrelationId::new());
+                        let transfer_prepare = transfer::TransferPrepareRequest::new(
+                            transfer_prepare_fulfil_args.from,
+                            transfer_prepare_fulfil_args.to,
+                            *amount,
+                            transfer_prepare_fulfil_args.currency,
+                            Some(transfer_id),
+                        );
+                        clients.transfer().await?.send(transfer_prepare).await?;
+                        output.info(format!("Prepared {}", transfer_id));
+                        transfer_ids.push(transfer_id);
+                    }
+
+                    tokio::time::sleep(std::time::Duration::from_millis(transfer_prepare_fulfil_args.wait)).await;
+
+                    let mut fulfilled = Vec::new();
+                    for transfer_id in transfer_ids {
+                        let transfer_fulfil = transfer::TransferFulfilRequest::new(
+                            transfer_prepare_fulfil_args.from,
+                            transfer_prepare_fulfil_args.to,
+                            transfer_prepare_fulfil_args.state,
+                            transfer_id,
+                        );
+                        clients.transfer().await?.send(transfer_fulfil).await?;
+                        fulfilled.push(output::TransferFulfilled {
+                            transfer_id,
+                            state: transfer_prepare_fulfil_args.state,
+                        });
+                    }
+                    output.emit(&fulfilled, |fulfilled| {
+                        for f in fulfilled {
+                            println!("{} {:?}", f.transfer_id, f.state);
+                        }
+                        Ok(())
+                    })?;
                 }
             }
         }
 
         SubCommand::Hub(hub_args) => {
-            let mut ml_central_ledger = mojaloop_api::clients::central_ledger::Client::from_k8s_params(
-                Some(client),
-                &opts.namespace,
-            ).await?;
             match hub_args.subcmd {
                 HubSubCommand::SettlementModel(hub_settlement_model_args) => {
                     match hub_settlement_model_args.subcmd {
                         SettlementModelSubCommand::Create(hub_settlement_model_create_args) => {
-                            let request = settlement_models::PostSettlementModel {
-                                settlement_model: settlement_models::SettlementModel {
-                                    auto_position_reset: hub_settlement_model_create_args.auto_position_reset,
-                                    ledger_account_type: hub_settlement_model_create_args.ledger_account_type,
-                                    settlement_account_type: hub_settlement_model_create_args.settlement_account_type,
-                                    name: hub_settlement_model_create_args.name.clone(),
-                                    require_liquidity_check: hub_settlement_model_create_args.require_liquidity_check,
-                                    settlement_delay: hub_settlement_model_create_args.settlement_delay,
-                                    settlement_granularity: hub_settlement_model_create_args.settlement_granularity,
-                                    settlement_interchange: hub_settlement_model_create_args.settlement_interchange,
-                                    currency: hub_settlement_model_create_args.currency,
-                                }
+                            let settlement_model = settlement_models::SettlementModel {
+                                auto_position_reset: hub_settlement_model_create_args.auto_position_reset,
+                                ledger_account_type: hub_settlement_model_create_args.ledger_account_type,
+                                settlement_account_type: hub_settlement_model_create_args.settlement_account_type,
+                                name: hub_settlement_model_create_args.name,
+                                require_liquidity_check: hub_settlement_model_create_args.require_liquidity_check,
+                                settlement_delay: hub_settlement_model_create_args.settlement_delay,
+                                settlement_granularity: hub_settlement_model_create_args.settlement_granularity,
+                                settlement_interchange: hub_settlement_model_create_args.settlement_interchange,
+                                currency: hub_settlement_model_create_args.currency,
+                            };
+                            let existing_models = if opts.strict {
+                                Vec::new()
+                            } else {
+                                clients.central_ledger().await?
+                                    .send(settlement_models::GetSettlementModels {}).await?.des().await?
+                            };
+                            let existing = existing_models.iter().find(|m| m.name == settlement_model.name);
+                            if let Some(m) = existing.filter(|m| m.currency != settlement_model.currency) {
+                                return Err(MojaloopCliError::SettlementModelConflict(
+                                    m.name.to_string(),
+                                    m.currency,
+                                    settlement_model.currency,
+                                ).into());
+                            }
+                            let operations = if existing.is_some() {
+                                output.info(format!(
+                                    "Settlement model {} for currency {} already exists.",
+                                    settlement_model.name,
+                                    settlement_model.currency,
+                                ));
+                                Vec::new()
+                            } else {
+                                vec![operations::Operation::CreateSettlementModel { settlement_model }]
                             };
-                            ml_central_ledger.send(request).await?;
-                            println!("Created settlement model: {}", hub_settlement_model_create_args.name);
-                            // Ok(())
+                            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
+                        }
+
+                        SettlementModelSubCommand::List => {
+                            let request = settlement_models::GetSettlementModels {};
+                            let models = clients.central_ledger().await?.send(request).await?.des().await?;
+                            output.emit(&models, |models| {
+                                let table = models.iter()
+                                    .map(|m| vec![
+                                        m.name.cell(),
+                                        m.currency.cell(),
+                                        format!("{:?}", m.settlement_delay).cell(),
+                                        format!("{:?}", m.settlement_granularity).cell(),
+                                        format!("{:?}", m.settlement_interchange).cell(),
+                                        format!("{:?}", m.ledger_account_type).cell(),
+                                        format!("{:?}", m.settlement_account_type).cell(),
+                                    ])
+                                    .table()
+                                    .title(vec![
+                                        "Name".cell(),
+                                        "Currency".cell(),
+                                        "Delay".cell(),
+                                        "Granularity".cell(),
+                                        "Interchange".cell(),
+                                        "Ledger account type".cell(),
+                                        "Settlement account type".cell(),
+                                    ]);
+                                print_stdout(table)?;
+                                Ok(())
+                            })?;
+                        }
+
+                        SettlementModelSubCommand::Get(get_args) => {
+                            let model = find_settlement_model(&mut clients, &get_args.name).await?;
+                            output.emit(&model, |m| {
+                                println!("Name: {}", m.name);
+                                println!("Currency: {}", m.currency);
+                                println!("Settlement delay: {:?}", m.settlement_delay);
+                                println!("Settlement granularity: {:?}", m.settlement_granularity);
+                                println!("Settlement interchange: {:?}", m.settlement_interchange);
+                                println!("Ledger account type: {:?}", m.ledger_account_type);
+                                println!("Settlement account type: {:?}", m.settlement_account_type);
+                                println!("Auto position reset: {}", m.auto_position_reset);
+                                println!("Require liquidity check: {}", m.require_liquidity_check);
+                                Ok(())
+                            })?;
                         }
                     }
                 }
@@ -990,73 +2088,63 @@ async fn main() -> anyhow::Result<()> {
                 HubSubCommand::Accounts(hub_accs_args) => {
                     match hub_accs_args.subcmd {
                         HubAccountsSubCommand::Create(hub_accs_create_args) => {
-                            async fn create_hub_account(
-                                client: &mut mojaloop_api::clients::central_ledger::Client,
-                                currency: Currency,
-                                r#type: HubAccountType
-                            ) -> fspiox_api::clients::Result<()> {
-                                let request = PostHubAccount {
-                                    // TODO: parametrise hub name?
-                                    name: FspId::from("Hub").unwrap(),
-                                    account: HubAccount {
-                                        r#type,
-                                        currency,
-                                    }
-                                };
-
-                                client.send(request).await?;
-
-                                let str_hub_acc_type = match r#type {
-                                    HubAccountType::HubMultilateralSettlement => "settlement",
-                                    HubAccountType::HubReconciliation => "reconciliation",
-                                };
-                                println!("Created hub {} account: {}", str_hub_acc_type, currency);
-                                Ok(())
+                            let (mut currencies, types) = match hub_accs_create_args.subcmd {
+                                HubAccountsCreateSubCommand::Reconciliation(args) =>
+                                    (args.currencies, &[HubAccountType::HubReconciliation][..]),
+                                HubAccountsCreateSubCommand::Settlement(args) =>
+                                    (args.currencies, &[HubAccountType::HubMultilateralSettlement][..]),
+                                HubAccountsCreateSubCommand::All(args) =>
+                                    (args.currencies, &[HubAccountType::HubReconciliation, HubAccountType::HubMultilateralSettlement][..]),
+                            };
+                            if currencies.is_empty() {
+                                currencies.push(currency_or_default(None, default_currency)?);
                             }
-                            match hub_accs_create_args.subcmd {
-                                HubAccountsCreateSubCommand::Reconciliation(hub_accs_create_rec_args) => {
-                                    for currency in &hub_accs_create_rec_args.currencies {
-                                        create_hub_account(&mut ml_central_ledger, *currency, HubAccountType::HubReconciliation).await?;
-                                    }
-                                }
-                                HubAccountsCreateSubCommand::Settlement(hub_accs_create_sett_args) => {
-                                    for currency in &hub_accs_create_sett_args.currencies {
-                                        create_hub_account(&mut ml_central_ledger, *currency, HubAccountType::HubMultilateralSettlement).await?;
-                                    }
-                                }
-                                HubAccountsCreateSubCommand::All(hub_accs_create_all_args) => {
-                                    for currency in &hub_accs_create_all_args.currencies {
-                                        create_hub_account(&mut ml_central_ledger, *currency, HubAccountType::HubReconciliation).await?;
-                                        create_hub_account(&mut ml_central_ledger, *currency, HubAccountType::HubMultilateralSettlement).await?;
+                            let existing_accounts = if opts.strict {
+                                Vec::new()
+                            } else {
+                                let hub = clients.hub();
+                                clients.central_ledger().await?.send(GetDfspAccounts { name: hub }).await?.des().await?
+                            };
+                            let operations = currencies.iter()
+                                .flat_map(|currency| types.iter().map(move |r#type| (*currency, *r#type)))
+                                .filter(|(currency, r#type)| {
+                                    let exists = existing_accounts.iter().any(|a|
+                                        a.currency == *currency && a.ledger_account_type == diff::hub_account_ledger_type(*r#type)
+                                    );
+                                    if exists {
+                                        output.info(format!("{:?} account for currency {} already exists.", r#type, currency));
                                     }
-                                }
-                            }
+                                    !exists
+                                })
+                                .map(|(currency, r#type)| operations::Operation::CreateHubAccount { r#type, currency })
+                                .collect();
+                            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
                         }
                         HubAccountsSubCommand::List => {
-                            // TODO: might need to take hub name as a parameter, in order to
-                            // support newer and older hub names of "hub" and "Hub"? Or just don't
-                            // support old hub name? Or just try both?
-                            let request = GetDfspAccounts { name: FspId::from("Hub").unwrap() };
-                            let accounts = ml_central_ledger.send(request).await?.des().await?;
-                            let table = accounts.iter()
-                                .map(|a| vec![
-                                    a.ledger_account_type.cell(),
-                                    a.currency.cell(),
-                                    (if a.is_active == 1 { true } else { false }).cell(),
-                                    a.changed_date.cell(),
-                                    a.value.cell(),
-                                    a.reserved_value.cell(),
-                                ])
-                                .table()
-                                .title(vec![
-                                    "Account type".cell(),
-                                    "Currency".cell(),
-                                    "Active".cell(),
-                                    "Changed date".cell(),
-                                    "Notification threshold".cell(),
-                                    "Reserved value".cell(),
-                                ]);
-                            print_stdout(table)?;
+                            let request = GetDfspAccounts { name: clients.hub() };
+                            let accounts = clients.central_ledger().await?.send(request).await?.des().await?;
+                            output.emit(&accounts, |accounts| {
+                                let table = accounts.iter()
+                                    .map(|a| vec![
+                                        a.ledger_account_type.cell(),
+                                        a.currency.cell(),
+                                        (if a.is_active == 1 { true } else { false }).cell(),
+                                        a.changed_date.cell(),
+                                        a.value.cell(),
+                                        a.reserved_value.cell(),
+                                    ])
+                                    .table()
+                                    .title(vec![
+                                        "Account type".cell(),
+                                        "Currency".cell(),
+                                        "Active".cell(),
+                                        "Changed date".cell(),
+                                        "Notification threshold".cell(),
+                                        "Reserved value".cell(),
+                                    ]);
+                                print_stdout(table)?;
+                                Ok(())
+                            })?;
                         }
                     }
                 }
@@ -1064,14 +2152,10 @@ async fn main() -> anyhow::Result<()> {
         }
 
         SubCommand::Participants(ps_args) => {
-            let mut ml_central_ledger = mojaloop_api::clients::central_ledger::Client::from_k8s_params(
-                Some(client),
-                &opts.namespace,
-            ).await?;
             match ps_args.subcmd {
                 ParticipantsSubCommand::List => {
                     let request = GetParticipants {};
-                    let participants = ml_central_ledger.send(request).await?.des().await?;
+                    let participants = clients.central_ledger().await?.send(request).await?.des().await?;
                     // TODO:
                     // 0. _really_ compress the output here, it's so sparse, making it quite
                     //    difficult to consume
@@ -1080,33 +2164,32 @@ async fn main() -> anyhow::Result<()> {
                     //     -f format
                     //     -a detailed account info
 
-                    for p in participants {
-                        println!(
-                            "Name: {}. Active: {}. Created: {}.",
-                            p.name,
-                            if p.is_active == 1 { true } else { false },
-                            p.created,
-                        );
-                        let table = p.accounts.iter().map(|a| vec![
-                            a.ledger_account_type.cell(),
-                            a.currency.cell(),
-                            (if a.is_active == 1 { true } else { false }).cell(),
-                        ])
-                            .table()
-                            .title(vec!["Account type".cell(), "Currency".cell(), "Active".cell()]);
+                    output.emit(&participants, |participants| {
+                        for p in participants {
+                            println!(
+                                "Name: {}. Active: {}. Created: {}.",
+                                p.name,
+                                if p.is_active == 1 { true } else { false },
+                                p.created,
+                            );
+                            let table = p.accounts.iter().map(|a| vec![
+                                a.ledger_account_type.cell(),
+                                a.currency.cell(),
+                                (if a.is_active == 1 { true } else { false }).cell(),
+                            ])
+                                .table()
+                                .title(vec!["Account type".cell(), "Currency".cell(), "Active".cell()]);
 
-                        print_stdout(table)?;
-                        println!("");
-                    }
+                            print_stdout(table)?;
+                            println!("");
+                        }
+                        Ok(())
+                    })?;
                 }
             }
         }
 
         SubCommand::Participant(p_args) => {
-            let mut ml_central_ledger = mojaloop_api::clients::central_ledger::Client::from_k8s_params(
-                Some(client.clone()),
-                &opts.namespace,
-            ).await?;
             match &p_args.subcmd {
                 ParticipantSubCommand::Limits(participant_limits_args) => {
                     match &participant_limits_args.subcmd {
@@ -1115,53 +2198,34 @@ async fn main() -> anyhow::Result<()> {
                                 name: p_args.name.clone(),
                             };
 
-                            let limits = ml_central_ledger.send(request).await?.des().await?;
-                            let table = limits.iter()
-                                .map(|l| vec![
-                                    l.currency.cell(),
-                                    l.limit.r#type.cell(),
-                                    l.limit.value.cell(),
-                                ])
-                                .table()
-                                .title(vec![
-                                    "Currency".cell(),
-                                    "Type".cell(),
-                                    "Value".cell(),
-                                ]);
-                            print_stdout(table)?;
+                            let limits = clients.central_ledger().await?.send(request).await?.des().await?;
+                            output.emit(&limits, |limits| {
+                                let table = limits.iter()
+                                    .map(|l| vec![
+                                        l.currency.cell(),
+                                        l.limit.r#type.cell(),
+                                        l.limit.value.cell(),
+                                    ])
+                                    .table()
+                                    .title(vec![
+                                        "Currency".cell(),
+                                        "Type".cell(),
+                                        "Value".cell(),
+                                    ]);
+                                print_stdout(table)?;
+                                Ok(())
+                            })?;
                         }
 
                         ParticipantLimitsSubCommand::Set(participant_limits_set_args) => {
-                            let request = participants::PutParticipantLimit {
-                                name: p_args.name.clone(),
-                                limit: participants::NewParticipantLimit {
-                                    currency: participant_limits_set_args.currency,
-                                    limit: participants::ParticipantLimit {
-                                        value: participant_limits_set_args.value,
-                                        r#type: participants::LimitType::NetDebitCap,
-                                        alarm_percentage: 10, // TODO: expose this to the user?
-                                    }
-                                }
-                            };
-                            match ml_central_ledger.send(request).await {
-                                Err(e) => {
-                                    println!(
-                                        "Failed to update {} {} limit to {}: {:?}\n",
-                                        p_args.name,
-                                        participant_limits_set_args.currency,
-                                        participant_limits_set_args.value,
-                                        e,
-                                    );
-                                }
-                                _ => {
-                                    println!(
-                                        "Updated {} {} limit to {}\n",
-                                        p_args.name,
-                                        participant_limits_set_args.currency,
-                                        participant_limits_set_args.value,
-                                    );
-                                }
-                            }
+                            let operations = vec![
+                                operations::Operation::SetNetDebitCap {
+                                    participant: p_args.name.clone(),
+                                    currency: currency_or_default(participant_limits_set_args.currency, default_currency)?,
+                                    value: participant_limits_set_args.value,
+                                },
+                            ];
+                            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
                         }
                     }
                 }
@@ -1171,205 +2235,271 @@ async fn main() -> anyhow::Result<()> {
                             let request = GetCallbackUrls {
                                 name: p_args.name.clone(),
                             };
-                            let endpoints = ml_central_ledger.send(request).await?.des().await?;
+                            let endpoints = clients.central_ledger().await?.send(request).await?.des().await?;
                             // TODO: table
-                            for ep in endpoints.iter() {
-                                println!("{} {}", ep.r#type, ep.value);
-                            }
+                            output.emit(&endpoints, |endpoints| {
+                                for ep in endpoints.iter() {
+                                    println!("{} {}", ep.r#type, ep.value);
+                                }
+                                Ok(())
+                            })?;
                         },
 
                         ParticipantEndpointsSubCommand::Set(participant_endpoints_set_args) => {
                             match &participant_endpoints_set_args.subcmd {
                                 ParticipantEndpointsSetSubCommand::All(participant_endpoints_set_all_args) => {
-                                    set_participant_endpoints(
+                                    let operations = operations::set_endpoints(
                                         &p_args.name,
-                                        &participant_endpoints_set_all_args.url.to_string(),
-                                        &mut ml_central_ledger,
-                                    ).await?;
+                                        participant_endpoints_set_all_args.url.as_str(),
+                                    );
+                                    perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
                                 }
                             }
                         },
                     }
                 }
 
-                ParticipantSubCommand::Onboard(participant_create_args) => {
+                ParticipantSubCommand::Onboard(participant_onboard_args) => {
+                    let participant_create_args = participant_onboard_args.parse(default_currency)?;
+                    amount::check_precision(participant_create_args.position, participant_create_args.currency)?;
                     let request = GetParticipants {};
-                    let existing_participants = ml_central_ledger.send(request).await?.des().await?;
+                    let existing_participants = clients.central_ledger().await?.send(request).await?.des().await?;
 
-                    match existing_participants.iter().find(|p| p.name == p_args.name) {
+                    let operations = match existing_participants.iter().find(|p| p.name == p_args.name) {
+                        Some(existing_participant) if opts.strict => {
+                            return Err(MojaloopCliError::ParticipantExists(existing_participant.name.clone()).into());
+                        },
                         Some(existing_participant) => {
-                            println!("Participant {} already exists.", existing_participant.name);
+                            output.info(format!("Participant {} already exists.", existing_participant.name));
+                            Vec::new()
                         },
                         None => {
-                            let post_participants_request = PostParticipant {
-                                participant: NewParticipant {
-                                    name: p_args.name.clone(),
+                            let mut operations = vec![
+                                operations::Operation::CreateParticipantAccount {
+                                    participant: p_args.name.clone(),
                                     currency: participant_create_args.currency,
                                 },
-                            };
-                            let post_participants_result = ml_central_ledger.send(post_participants_request).await?.des().await?;
-                            println!("Post participants result:\n{:?}", post_participants_result);
-
-                            let post_initial_position_and_limits_req = PostInitialPositionAndLimits {
-                                name: p_args.name.clone(),
-                                initial_position_and_limits: InitialPositionAndLimits {
+                                operations::Operation::SetInitialPositionAndLimits {
+                                    participant: p_args.name.clone(),
                                     currency: participant_create_args.currency,
-                                    limit: Limit {
-                                        r#type: LimitType::NetDebitCap,
-                                        value: participant_create_args.ndc,
-                                    },
-                                    initial_position: participant_create_args.position,
-                                }
-                            };
-
-                            ml_central_ledger.send(post_initial_position_and_limits_req).await?;
-
-                            set_participant_endpoints(
+                                    ndc: participant_create_args.ndc,
+                                    position: participant_create_args.position,
+                                },
+                            ];
+                            operations.extend(operations::set_endpoints(
                                 &p_args.name,
-                                &participant_create_args.url.to_string(),
-                                &mut ml_central_ledger,
-                            ).await?;
+                                participant_create_args.url.as_str(),
+                            ));
+                            operations
                         },
+                    };
+                    let currency = participant_create_args.currency;
+                    let mut plan = Vec::new();
+                    if participant_onboard_args.ensure_hub_accounts && !operations.is_empty() {
+                        plan.extend(hub_currency_operations(&mut clients, currency).await?);
+                    }
+                    plan.extend(operations.iter().cloned());
+                    match perform(plan, &mut clients, opts.dry_run, opts.strict, &output).await {
+                        // Offer to do what --ensure-hub-accounts would have done, then try again
+                        Err(e) if hints::hub_accounts_missing(&e) && !output.is_json() && prompt::interactive() => {
+                            eprintln!("The hub has no {} reconciliation or multilateral settlement account.", currency);
+                            let question = format!(
+                                "Create them, and a DEFERREDNET{} settlement model, then retry onboarding?",
+                                currency,
+                            );
+                            if !prompt::confirm(&question)? {
+                                return Err(e);
+                            }
+                            let hub_operations = hub_currency_operations(&mut clients, currency).await?;
+                            perform(hub_operations, &mut clients, false, opts.strict, &output).await?;
+                            perform(operations, &mut clients, false, opts.strict, &output).await?;
+                        },
+                        result => { result?; },
                     }
                 }
 
                 ParticipantSubCommand::Accounts(pa) => {
                     match &pa.subcmd {
                         ParticipantAccountsSubCommand::Fund(part_acc_fund_args) => {
+                            let currency = currency_or_default(part_acc_fund_args.currency, default_currency)?;
                             let get_accounts = participants::GetDfspAccounts{
                                 name: p_args.name.clone(),
                             };
-                            let accounts = ml_central_ledger.send(get_accounts).await?.des().await?;
+                            let accounts = clients.central_ledger().await?.send(get_accounts).await?.des().await?;
                             let account = accounts
                                 .iter()
                                 .find(|acc|
                                     acc.ledger_account_type == participants::AnyAccountType::Settlement &&
-                                    acc.currency == part_acc_fund_args.currency,
+                                    acc.currency == currency,
                                 )
                                 .map(Ok)
                                 .unwrap_or(Err(MojaloopCliError::ParticipantMissingCurrencyAccount(
-                                            p_args.name.clone(), part_acc_fund_args.currency)))?;
-                            match &part_acc_fund_args.subcmd {
+                                            p_args.name.clone(), currency)))?;
+                            let funds = operations::Funds {
+                                participant: p_args.name,
+                                account_id: account.id,
+                                currency,
+                                reason: part_acc_fund_args.reason.clone(),
+                                external_reference: part_acc_fund_args.external_reference.clone(),
+                            };
+                            let operations = match &part_acc_fund_args.subcmd {
                                 ParticipantAccountFundSubCommand::In(part_acc_fund_in_args) => {
-                                    println!("Not yet implemented")
+                                    if part_acc_fund_in_args.amount <= Amount::ZERO {
+                                        return Err(MojaloopCliError::NonPositiveAmount(part_acc_fund_in_args.amount).into());
+                                    }
+                                    amount::check_precision(part_acc_fund_in_args.amount, currency)?;
+                                    funds.r#in(part_acc_fund_in_args.amount)
                                 },
                                 ParticipantAccountFundSubCommand::Out(part_acc_fund_out_args) => {
-                                    println!("Not yet implemented")
+                                    if part_acc_fund_out_args.amount <= Amount::ZERO {
+                                        return Err(MojaloopCliError::NonPositiveAmount(part_acc_fund_out_args.amount).into());
+                                    }
+                                    amount::check_precision(part_acc_fund_out_args.amount, currency)?;
+                                    funds.out(part_acc_fund_out_args.amount, part_acc_fund_out_args.reserve_only)
+                                },
+                                ParticipantAccountFundSubCommand::Commit(part_acc_fund_commit_args) => {
+                                    vec![funds.finalise_out(
+                                        part_acc_fund_commit_args.transfer_id,
+                                        participants::ParticipantFundsInOutAction::RecordFundsOutCommit,
+                                    )]
+                                },
+                                ParticipantAccountFundSubCommand::Abort(part_acc_fund_abort_args) => {
+                                    vec![funds.finalise_out(
+                                        part_acc_fund_abort_args.transfer_id,
+                                        participants::ParticipantFundsInOutAction::RecordFundsOutAbort,
+                                    )]
                                 },
                                 ParticipantAccountFundSubCommand::Num(part_acc_fund_num_args) => {
-                                    let action = if part_acc_fund_num_args.amount > Amount::ZERO {
-                                        participants::ParticipantFundsInOutAction::RecordFundsIn
+                                    amount::check_precision(part_acc_fund_num_args.amount, currency)?;
+                                    if part_acc_fund_num_args.amount > Amount::ZERO {
+                                        funds.r#in(part_acc_fund_num_args.amount)
                                     } else {
-                                        participants::ParticipantFundsInOutAction::RecordFundsOutPrepareReserve
-                                    };
-                                    let transfer_id = fspiox_api::CorrelationId::new();
-                                    let funds_request = participants::PostParticipantSettlementFunds {
-                                        name: p_args.name,
-                                        account_id: account.id,
-                                        funds: participants::ParticipantFundsInOut {
-                                            transfer_id: transfer_id.clone(),
-                                            action,
-                                            amount: fspiox_api::Money {
-                                                currency: part_acc_fund_args.currency,
-                                                amount: part_acc_fund_num_args.amount.abs()
-                                            },
-                                            reason: "Voodoo".to_string(),
-                                            external_reference: "Voodoo".to_string(),
-                                        }
-                                    };
-                                    ml_central_ledger.send(funds_request).await?;
-                                    println!(
-                                        "Sent funds request of {} with transfer ID {}",
-                                        part_acc_fund_num_args.amount,
-                                        transfer_id
-                                    );
+                                        funds.out(part_acc_fund_num_args.amount.abs(), false)
+                                    }
                                 }
-                            }
+                            };
+                            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
                         }
 
                         ParticipantAccountsSubCommand::List => {
                             let request = GetDfspAccounts { name: p_args.name };
-                            let accounts = ml_central_ledger.send(request).await?.des().await?;
+                            let accounts = clients.central_ledger().await?.send(request).await?.des().await?;
                             // TODO: table
-                            for acc in accounts {
-                                println!(
-                                    "{} {} {} Active: {}",
-                                    acc.currency,
-                                    acc.ledger_account_type,
-                                    acc.value,
-                                    (if acc.is_active == 1 { true } else { false }),
-                                );
-                            }
+                            output.emit(&accounts, |accounts| {
+                                for acc in accounts {
+                                    println!(
+                                        "{} {} {} Active: {}",
+                                        acc.currency,
+                                        acc.ledger_account_type,
+                                        acc.value,
+                                        (if acc.is_active == 1 { true } else { false }),
+                                    );
+                                }
+                                Ok(())
+                            })?;
                         }
 
                         ParticipantAccountsSubCommand::Enable(acc_enable_args) => {
                             let get_accs_request = GetDfspAccounts { name: p_args.name };
-                            let accounts = ml_central_ledger.send(get_accs_request).await?.des().await?;
+                            let accounts = clients.central_ledger().await?.send(get_accs_request).await?.des().await?;
+                            let mut operations = Vec::new();
                             for curr in &acc_enable_args.currency {
                                 let currency_acc = accounts.iter().find(|acc|
                                     acc.currency == *curr && acc.ledger_account_type == AnyAccountType::Position
                                 );
                                 match currency_acc {
                                     Some(acc) => {
-                                        let enable_request = PutParticipantAccount {
+                                        operations.push(operations::Operation::SetAccountActive {
+                                            participant: p_args.name,
+                                            currency: *curr,
                                             account_id: acc.id,
-                                            name: p_args.name,
-                                            set_active: true,
-                                        };
-                                        ml_central_ledger.send(enable_request).await?;
-                                        println!(
-                                            "Enabled {} account {} for currency {}",
-                                            p_args.name,
-                                            acc.id,
-                                            curr,
-                                        );
+                                            active: true,
+                                        });
                                     }
                                     None => {
-                                        println!("Couldn't find account for currency {}", curr);
+                                        output.info(format!("Couldn't find account for currency {}", curr));
                                     }
                                 }
                             }
+                            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
                         }
 
                         ParticipantAccountsSubCommand::Disable(acc_disable_args) => {
                             let get_accs_request = GetDfspAccounts { name: p_args.name };
-                            let accounts = ml_central_ledger.send(get_accs_request).await?.des().await?;
+                            let accounts = clients.central_ledger().await?.send(get_accs_request).await?.des().await?;
+                            let mut operations = Vec::new();
                             for curr in &acc_disable_args.currency {
                                 let currency_acc = accounts.iter().find(|acc| acc.currency == *curr);
                                 match currency_acc {
                                     Some(acc) => {
-                                        let enable_request = PutParticipantAccount {
+                                        operations.push(operations::Operation::SetAccountActive {
+                                            participant: p_args.name,
+                                            currency: *curr,
                                             account_id: acc.id,
-                                            name: p_args.name,
-                                            set_active: false,
-                                        };
-                                        ml_central_ledger.send(enable_request).await?;
-                                        println!(
-                                            "Disabled {} account {} for currency {}",
-                                            p_args.name,
-                                            acc.id,
-                                            curr,
-                                        );
+                                            active: false,
+                                        });
                                     }
                                     None => {
-                                        println!("Couldn't find account for currency {}", curr);
+                                        output.info(format!("Couldn't find account for currency {}", curr));
                                     }
                                 }
                             }
+                            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
                         }
 
                         ParticipantAccountsSubCommand::Upsert(acc) => {
-                            println!("Not yet implemented");
-                            // 1. get participant, make an error if it doesn't exist
-                            // 2. get existing accounts
-                            // 3. check what was requested, compare with what we have
-                        // let existing_accounts_in_switch =
-                        //     send(
-                        //         http_client,
-                        //         host,
-                        //         GetDfspAccounts { name: name.to_string() }
-                        //     )
-                        //     .await
+                            let currency = currency_or_default(acc.currency, default_currency)?;
+                            if let Some(position) = acc.position {
+                                amount::check_precision(position, currency)?;
+                            }
+                            let ml_central_ledger = clients.central_ledger().await?;
+                            let existing_participants = ml_central_ledger.send(GetParticipants {}).await?.des().await?;
+                            let participant = existing_participants.iter()
+                                .find(|p| p.name == p_args.name)
+                                .ok_or(MojaloopCliError::ParticipantNotFound(p_args.name.clone()))?;
+                            let existing_account = participant.accounts.iter().find(|a|
+                                a.currency == currency && a.ledger_account_type == AnyAccountType::Position
+                            );
+                            let operations = match existing_account {
+                                None => vec![
+                                    operations::Operation::CreateParticipantAccount {
+                                        participant: p_args.name.clone(),
+                                        currency,
+                                    },
+                                    operations::Operation::SetInitialPositionAndLimits {
+                                        participant: p_args.name.clone(),
+                                        currency,
+                                        ndc: acc.ndc.unwrap_or(0),
+                                        position: acc.position.unwrap_or(Amount::ZERO),
+                                    },
+                                ],
+                                Some(_) => {
+                                    if acc.position.is_some() {
+                                        output.info(format!(
+                                            "{} {} account exists, its position will not be modified",
+                                            p_args.name,
+                                            currency,
+                                        ));
+                                    }
+                                    let limits = ml_central_ledger
+                                        .send(participants::GetParticipantLimits { name: p_args.name.clone() })
+                                        .await?.des().await?;
+                                    let current_ndc = limits.iter()
+                                        .find(|l| l.currency == currency && l.limit.r#type == participants::LimitType::NetDebitCap)
+                                        .map(|l| l.limit.value);
+                                    match acc.ndc {
+                                        Some(ndc) if current_ndc != Some(ndc) => vec![
+                                            operations::Operation::SetNetDebitCap {
+                                                participant: p_args.name.clone(),
+                                                currency,
+                                                value: ndc,
+                                            },
+                                        ],
+                                        _ => Vec::new(),
+                                    }
+                                }
+                            };
+                            perform(operations, &mut clients, opts.dry_run, opts.strict, &output).await?;
                         }
                     }
                 }
@@ -1390,32 +2520,47 @@ async fn main() -> anyhow::Result<()> {
             use futures::SinkExt;
             use voodoo_doll::Message;
 
+            if opts.dry_run {
+                return Err(MojaloopCliError::DryRunUnsupported.into());
+            }
+
             // TODO: it's really not this module's job to know how to deploy voodoo-doll, where and
             // how to find it once it's deployed, and how to destroy it. That should be delegated
             // to the voodoo-doll module.
 
             let destroy = voodoo_args.destroy && voodoo_args.subcmd != VoodooSubCommand::Destroy;
+            let mut transfer_failed = false;
 
             match voodoo_args.subcmd.clone() {
                 VoodooSubCommand::Destroy => {
-                    voodoo_doll::destroy(Some(client.clone()), &opts.namespace).await?;
+                    voodoo_doll::destroy(Some(clients.k8s()?), clients.namespace()).await?;
+                    output.emit(&output::VoodooDeployment { deployed: false }, |_| Ok(()))?;
                 }
 
                 VoodooSubCommand::Deploy => {
-                    voodoo_doll::create(Some(client.clone()), &opts.namespace).await?;
+                    voodoo_doll::create(Some(clients.k8s()?), clients.namespace()).await?;
+                    output.emit(&output::VoodooDeployment { deployed: true }, |_| Ok(()))?;
                 }
 
                 VoodooSubCommand::Transfer(voodoo_transfer_args) => {
-                    let (mut voodoo_write, mut voodoo_read) = voodoo_doll::get_pod_stream(Some(client.clone())).await?.split();
-                    let transfer_id = voodoo_transfer_args.transfer_id.unwrap_or(
+                    let (currency, amount, transfer_id) = voodoo_transfer_args.parse(default_currency)?;
+                    amount::check_precision(amount, currency)?;
+                    let (mut voodoo_write, mut voodoo_read) = voodoo_doll::get_pod_stream(Some(clients.k8s()?)).await?.split();
+                    // Transfer errors are reported by the switch to the payer
+                    let hint_context = hints::Context {
+                        participant: Some(voodoo_transfer_args.payer.clone()),
+                        currency: Some(currency),
+                        ..hints::Context::default()
+                    };
+                    let transfer_id = transfer_id.unwrap_or(
                         transfer::TransferId(fspiox_api::CorrelationId::new()));
                     let mut transfers = Vec::new();
                     transfers.push(
                         vproto::TransferMessage {
                             msg_sender: voodoo_transfer_args.payer,
                             msg_recipient: voodoo_transfer_args.payee,
-                            currency: voodoo_transfer_args.currency,
-                            amount: voodoo_transfer_args.amount,
+                            currency,
+                            amount,
                             transfer_id,
                         }
                     );
@@ -1432,6 +2577,13 @@ async fn main() -> anyhow::Result<()> {
                         )
                     ).await?;
 
+                    let mut result = output::VoodooTransfer {
+                        transfer_id,
+                        complete: false,
+                        error: None,
+                        hint: None,
+                    };
+
                     while let Some(msg) = voodoo_read.next().await {
                         let msg = msg?;
                         match msg {
@@ -1442,13 +2594,20 @@ async fn main() -> anyhow::Result<()> {
                                 match response_msg.content {
                                     vproto::Notification::TransferComplete(tc) => {
                                         if tc.id == transfer_id {
-                                            println!("Transfer complete. ID: {}", transfer_id);
+                                            output.info(format!("Transfer complete. ID: {}", transfer_id));
+                                            result.complete = true;
                                             break;
                                         }
                                     }
                                     vproto::Notification::TransferError(te) => {
                                         if te.id == transfer_id {
-                                            println!("Transfer error. Error: {:?}", s);
+                                            output.info(format!("Transfer error. Error: {:?}", s));
+                                            let error = serde_json::from_str(&s)?;
+                                            result.hint = hints::hint_for_value(&error, &hint_context);
+                                            if let Some(hint) = &result.hint {
+                                                output.info(hint);
+                                            }
+                                            result.error = Some(error);
                                             break;
                                         }
                                     }
@@ -1458,19 +2617,28 @@ async fn main() -> anyhow::Result<()> {
                                 }
                             }
                             _ => {
-                                println!("Incoming non-text:");
-                                println!("{}", msg);
+                                output.info("Incoming non-text:");
+                                output.info(&msg);
                             }
                         }
                     }
 
                     // Cleanup
                     voodoo_write.close().await?;
+
+                    output.emit(&result, |_| Ok(()))?;
+                    transfer_failed = result.error.is_some();
                 }
             }
 
             if destroy {
-                voodoo_doll::destroy(Some(client), &opts.namespace).await?;
+                voodoo_doll::destroy(Some(clients.k8s()?), clients.namespace()).await?;
+            }
+
+            // The transfer document, including the error, has been printed, so we exit rather
+            // than return an error that would print a second document
+            if transfer_failed {
+                std::process::exit(1);
             }
 
             // TODO: check for an existing voodoo doll in the cluster
@@ -1514,3 +2682,100 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn xof() -> Currency {
+        "XOF".parse().unwrap()
+    }
+
+    fn is_url(s: &str) -> bool {
+        url::Url::parse(s).is_ok()
+    }
+
+    #[test]
+    fn split_currency_present() {
+        let args = args(&["sek", "http://payerfsp.sim"]);
+        let (currency, rest) = split_currency(&args, Some(xof()), is_url).unwrap();
+        assert_eq!(currency.to_string(), "SEK");
+        assert_eq!(rest, &args[1..]);
+    }
+
+    #[test]
+    fn split_currency_absent_with_default() {
+        let args = args(&["http://payerfsp.sim"]);
+        let (currency, rest) = split_currency(&args, Some(xof()), is_url).unwrap();
+        assert_eq!(currency, xof());
+        assert_eq!(rest, &args[..]);
+    }
+
+    #[test]
+    fn split_currency_absent_without_default() {
+        let args = args(&["http://payerfsp.sim"]);
+        assert!(matches!(split_currency(&args, None, is_url), Err(MojaloopCliError::CurrencyRequired)));
+        assert!(matches!(split_currency(&[], None, is_url), Err(MojaloopCliError::CurrencyRequired)));
+    }
+
+    #[test]
+    fn split_currency_unknown() {
+        let args = args(&["SEKK", "http://payerfsp.sim"]);
+        match split_currency(&args, Some(xof()), is_url) {
+            Err(MojaloopCliError::InvalidArgument(name, value, message)) => {
+                assert_eq!(name, "CURRENCY");
+                assert_eq!(value, "SEKK");
+                assert!(message.contains("Did you mean SEK"), "{}", message);
+            }
+            _ => panic!("SEKK was accepted as a currency, or taken for a url"),
+        }
+    }
+
+    #[test]
+    fn onboard_args() {
+        let onboard = ParticipantOnboard {
+            args: args(&["http://payerfsp.sim", "10,000"]),
+            ensure_hub_accounts: false,
+        };
+        let parsed = onboard.parse(Some(xof())).unwrap();
+        assert_eq!(parsed.currency, xof());
+        assert_eq!(parsed.url.as_str(), "http://payerfsp.sim/");
+        assert_eq!(parsed.ndc, 10_000);
+        assert_eq!(parsed.position.to_string(), Amount::ZERO.to_string());
+
+        let onboard = ParticipantOnboard { args: args(&["XOF"]), ensure_hub_accounts: false };
+        assert!(matches!(onboard.parse(None), Err(MojaloopCliError::MissingArgument(_))));
+
+        let onboard = ParticipantOnboard {
+            args: args(&["XOF", "http://payerfsp.sim", "0", "0", "extra"]),
+            ensure_hub_accounts: false,
+        };
+        assert!(matches!(onboard.parse(None), Err(MojaloopCliError::UnexpectedArgument(_))));
+    }
+
+    #[test]
+    fn voodoo_transfer_args() {
+        let transfer = |a: &[&str]| PuppetTransfer {
+            payer: FspId::from("payerfsp").unwrap(),
+            payee: FspId::from("payeefsp").unwrap(),
+            args: args(a),
+        };
+        let (currency, amount, transfer_id) = transfer(&["10"]).parse(Some(xof())).unwrap();
+        assert_eq!(currency, xof());
+        assert_eq!(amount.to_string(), "10");
+        assert!(transfer_id.is_none());
+
+        let (currency, _, _) = transfer(&["eur", "10"]).parse(Some(xof())).unwrap();
+        assert_eq!(currency.to_string(), "EUR");
+
+        assert!(matches!(transfer(&["10"]).parse(None), Err(MojaloopCliError::CurrencyRequired)));
+        assert!(matches!(
+            transfer(&["EUX", "10"]).parse(Some(xof())),
+            Err(MojaloopCliError::InvalidArgument(..)),
+        ));
+    }
+}
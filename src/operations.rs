@@ -0,0 +1,558 @@
+// Operations that modify the switch. Commands that modify the switch first assess what needs to be
+// done, producing a list of operations, then either print that list (in --dry-run mode) or
+// execute it. Operations are deliberately simple: each corresponds to a single request to a
+// Mojaloop service, so that the list presented to the user is exactly what will be sent.
+
+use anyhow::Context;
+use serde::Serialize;
+
+use fspiox_api::{Amount, CorrelationId, Currency, FspId, Money};
+use fspiox_api::transfer::{TransferId, TransferState};
+use mojaloop_api::{
+    central_ledger::participants::{
+        self,
+        FspiopCallbackType,
+        HubAccount,
+        HubAccountType,
+        InitialPositionAndLimits,
+        Limit,
+        LimitType,
+        NewParticipant,
+        ParticipantFundsInOutAction,
+        PostCallbackUrl,
+        PostHubAccount,
+        PostInitialPositionAndLimits,
+        PostParticipant,
+        PutParticipantAccount,
+        SettlementAccountId,
+    },
+    central_ledger::settlement_models,
+    central_ledger::transfers::GetTransfer,
+    clients::FspiopClient,
+    settlement::{settlement, settlement_windows},
+};
+
+use crate::clients::Clients;
+use crate::output::Output;
+use crate::MojaloopCliError;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Operation {
+    CreateHubAccount {
+        r#type: HubAccountType,
+        currency: Currency,
+    },
+    CreateSettlementModel {
+        settlement_model: settlement_models::SettlementModel,
+    },
+    /// Create a participant with an account in the given currency, or, where the participant
+    /// already exists, add an account in the given currency
+    CreateParticipantAccount {
+        participant: FspId,
+        currency: Currency,
+    },
+    SetInitialPositionAndLimits {
+        participant: FspId,
+        currency: Currency,
+        ndc: u32,
+        position: Amount,
+    },
+    SetAccountActive {
+        participant: FspId,
+        currency: Currency,
+        account_id: SettlementAccountId,
+        active: bool,
+    },
+    SetNetDebitCap {
+        participant: FspId,
+        currency: Currency,
+        value: u32,
+    },
+    SetEndpoint {
+        participant: FspId,
+        r#type: FspiopCallbackType,
+        url: String,
+    },
+    RecordFunds {
+        participant: FspId,
+        account_id: SettlementAccountId,
+        transfer_id: CorrelationId,
+        action: ParticipantFundsInOutAction,
+        amount: Money,
+        reason: String,
+        external_reference: String,
+    },
+    /// Commit or abort a funds out reservation, once it's RESERVED
+    FinaliseFundsOut {
+        participant: FspId,
+        account_id: SettlementAccountId,
+        transfer_id: CorrelationId,
+        action: ParticipantFundsInOutAction,
+        reason: String,
+    },
+    CloseSettlementWindow {
+        id: settlement_windows::SettlementWindowId,
+        reason: String,
+    },
+    CreateSettlement {
+        settlement_model: String,
+        reason: String,
+        settlement_windows: Vec<settlement_windows::SettlementWindowId>,
+    },
+    /// Move the given accounts of a settlement to the given state
+    UpdateSettlementAccounts {
+        settlement_id: settlement::SettlementId,
+        state: settlement::SettlementState,
+        accounts: Vec<SettlementAccountUpdate>,
+        reason: String,
+        external_reference: String,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SettlementAccountUpdate {
+    pub participant_id: settlement::ParticipantId,
+    pub account_id: settlement::ParticipantCurrencyId,
+}
+
+impl Operation {
+    /// Whether this operation creates something in the switch, as opposed to modifying something
+    /// that exists
+    pub fn creates(&self) -> bool {
+        matches!(
+            self,
+            Operation::CreateHubAccount { .. } |
+            Operation::CreateSettlementModel { .. } |
+            Operation::CreateParticipantAccount { .. }
+        )
+    }
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::CreateHubAccount { r#type, currency } => {
+                let str_hub_acc_type = match r#type {
+                    HubAccountType::HubMultilateralSettlement => "settlement",
+                    HubAccountType::HubReconciliation => "reconciliation",
+                };
+                write!(f, "Create hub {} account: {}", str_hub_acc_type, currency)
+            }
+            Operation::CreateSettlementModel { settlement_model } =>
+                write!(f, "Create settlement model {} for currency {}", settlement_model.name, settlement_model.currency),
+            Operation::CreateParticipantAccount { participant, currency } =>
+                write!(f, "Create participant {} with {} account", participant, currency),
+            Operation::SetInitialPositionAndLimits { participant, currency, ndc, position } =>
+                write!(f, "Set {} {} initial position to {} and NDC to {}", participant, currency, position, ndc),
+            Operation::SetAccountActive { participant, currency, account_id, active } =>
+                write!(
+                    f,
+                    "{} {} account {} for currency {}",
+                    if *active { "Enable" } else { "Disable" },
+                    participant,
+                    account_id,
+                    currency,
+                ),
+            Operation::SetNetDebitCap { participant, currency, value } =>
+                write!(f, "Set {} {} NDC to {}", participant, currency, value),
+            Operation::SetEndpoint { participant, r#type, url } =>
+                write!(f, "Set {} {:?} endpoint to {}", participant, r#type, url),
+            Operation::RecordFunds { participant, action, amount, transfer_id, .. } =>
+                write!(
+                    f,
+                    "Send {:?} funds request of {} {} for {} with transfer ID {}",
+                    action,
+                    amount.amount,
+                    amount.currency,
+                    participant,
+                    transfer_id,
+                ),
+            Operation::FinaliseFundsOut { participant, action, transfer_id, .. } =>
+                write!(f, "Send {:?} funds request for {} with transfer ID {}", action, participant, transfer_id),
+            Operation::CloseSettlementWindow { id, .. } =>
+                write!(f, "Close settlement window: {}", id),
+            Operation::CreateSettlement { settlement_model, settlement_windows, .. } =>
+                write!(
+                    f,
+                    "Create {} settlement from windows: {}",
+                    settlement_model,
+                    settlement_windows.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(", "),
+                ),
+            Operation::UpdateSettlementAccounts { settlement_id, state, accounts, .. } =>
+                write!(
+                    f,
+                    "Set settlement {} accounts {} to {:?}",
+                    settlement_id,
+                    accounts.iter().map(|a| a.account_id.to_string()).collect::<Vec<_>>().join(", "),
+                    state,
+                ),
+        }
+    }
+}
+
+/// An operation the switch refused. Attached as context to the error returned by `perform`, so
+/// that the error can be explained in terms of what was being attempted; see hints.rs.
+#[derive(Debug)]
+pub struct Failed(pub Operation);
+
+impl std::fmt::Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed: {}", self.0)
+    }
+}
+
+/// Anything returned by the switch as a result of an operation that's of interest to the user
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum Outcome {
+    Done,
+    Settlement(settlement::Settlement),
+}
+
+#[derive(Serialize, Debug)]
+pub struct Executed {
+    pub operation: Operation,
+    pub result: Outcome,
+}
+
+/// The result of a command that modifies the switch
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub dry_run: bool,
+    pub operations: Vec<Executed>,
+    /// Operations not performed because what they would create already exists
+    pub skipped: Vec<Operation>,
+}
+
+/// The operations required to set all endpoints of a participant to the given url
+pub fn set_endpoints(participant: &FspId, url: &str) -> Vec<Operation> {
+    use strum::IntoEnumIterator;
+    // TODO: strip trailing slash
+    FspiopCallbackType::iter()
+        .map(|r#type| Operation::SetEndpoint {
+            participant: participant.clone(),
+            r#type,
+            url: url.to_string(),
+        })
+        .collect()
+}
+
+/// The parameters common to all funds in and out operations on a given participant account
+pub struct Funds {
+    pub participant: FspId,
+    pub account_id: SettlementAccountId,
+    pub currency: Currency,
+    pub reason: String,
+    pub external_reference: String,
+}
+
+impl Funds {
+    fn record(&self, transfer_id: CorrelationId, action: ParticipantFundsInOutAction, amount: Amount) -> Operation {
+        Operation::RecordFunds {
+            participant: self.participant.clone(),
+            account_id: self.account_id,
+            transfer_id,
+            action,
+            amount: Money { currency: self.currency, amount },
+            reason: self.reason.clone(),
+            external_reference: self.external_reference.clone(),
+        }
+    }
+
+    /// The operations to process funds in
+    pub fn r#in(&self, amount: Amount) -> Vec<Operation> {
+        vec![self.record(CorrelationId::new(), ParticipantFundsInOutAction::RecordFundsIn, amount)]
+    }
+
+    /// The operations to reserve funds out and, unless `reserve_only`, commit the reservation
+    pub fn out(&self, amount: Amount, reserve_only: bool) -> Vec<Operation> {
+        let transfer_id = CorrelationId::new();
+        let mut operations = vec![
+            self.record(transfer_id, ParticipantFundsInOutAction::RecordFundsOutPrepareReserve, amount),
+        ];
+        if !reserve_only {
+            operations.push(self.finalise_out(transfer_id, ParticipantFundsInOutAction::RecordFundsOutCommit));
+        }
+        operations
+    }
+
+    /// The operation to commit or abort a funds out reservation
+    pub fn finalise_out(&self, transfer_id: CorrelationId, action: ParticipantFundsInOutAction) -> Operation {
+        Operation::FinaliseFundsOut {
+            participant: self.participant.clone(),
+            account_id: self.account_id,
+            transfer_id,
+            action,
+            reason: self.reason.clone(),
+        }
+    }
+}
+
+/// How long to wait for a funds out reservation to be RESERVED
+const RESERVATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Wait for a funds out reservation to be RESERVED. Central-ledger processes funds out requests
+/// asynchronously, so a reservation made by the preceding operation may not yet be RESERVED, or
+/// even exist, when we come to commit it.
+async fn wait_for_reservation(clients: &mut Clients, transfer_id: CorrelationId) -> anyhow::Result<()> {
+    let deadline = tokio::time::Instant::now() + RESERVATION_TIMEOUT;
+    loop {
+        let request = GetTransfer { id: TransferId(transfer_id) };
+        let state: anyhow::Result<TransferState> = async {
+            Ok(clients.central_ledger().await?.send(request).await?.des().await?.transfer_state)
+        }.await;
+        let expired = tokio::time::Instant::now() >= deadline;
+        match state {
+            Ok(TransferState::Reserved) => return Ok(()),
+            Ok(state @ TransferState::Committed) | Ok(state @ TransferState::Aborted) =>
+                return Err(MojaloopCliError::FundsOutNotReserved(transfer_id, state).into()),
+            // Until the reservation is processed central-ledger doesn't know the transfer
+            Err(e) if expired => return Err(e),
+            _ if expired =>
+                return Err(MojaloopCliError::FundsOutReservationTimeout(transfer_id, RESERVATION_TIMEOUT.as_secs()).into()),
+            _ => tokio::time::sleep(std::time::Duration::from_millis(500)).await,
+        }
+    }
+}
+
+pub async fn execute(op: &Operation, clients: &mut Clients) -> anyhow::Result<Outcome> {
+    match op {
+        Operation::CreateHubAccount { r#type, currency } => {
+            let request = PostHubAccount {
+                name: clients.hub(),
+                account: HubAccount {
+                    r#type: *r#type,
+                    currency: *currency,
+                }
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::CreateSettlementModel { settlement_model } => {
+            let request = settlement_models::PostSettlementModel {
+                settlement_model: settlement_model.clone(),
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::CreateParticipantAccount { participant, currency } => {
+            let request = PostParticipant {
+                participant: NewParticipant {
+                    name: participant.clone(),
+                    currency: *currency,
+                },
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::SetInitialPositionAndLimits { participant, currency, ndc, position } => {
+            let request = PostInitialPositionAndLimits {
+                name: participant.clone(),
+                initial_position_and_limits: InitialPositionAndLimits {
+                    currency: *currency,
+                    limit: Limit {
+                        r#type: LimitType::NetDebitCap,
+                        value: *ndc,
+                    },
+                    initial_position: *position,
+                }
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::SetAccountActive { participant, account_id, active, .. } => {
+            let request = PutParticipantAccount {
+                account_id: *account_id,
+                name: participant.clone(),
+                set_active: *active,
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::SetNetDebitCap { participant, currency, value } => {
+            let request = participants::PutParticipantLimit {
+                name: participant.clone(),
+                limit: participants::NewParticipantLimit {
+                    currency: *currency,
+                    limit: participants::ParticipantLimit {
+                        value: *value,
+                        r#type: participants::LimitType::NetDebitCap,
+                        alarm_percentage: 10, // TODO: expose this to the user?
+                    }
+                }
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::SetEndpoint { participant, r#type, url } => {
+            let request = PostCallbackUrl {
+                name: participant.clone(),
+                callback_type: *r#type,
+                hostname: url.clone(),
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::RecordFunds { participant, account_id, transfer_id, action, amount, reason, external_reference } => {
+            let request = participants::PostParticipantSettlementFunds {
+                name: participant.clone(),
+                account_id: *account_id,
+                funds: participants::ParticipantFundsInOut {
+                    transfer_id: *transfer_id,
+                    action: action.clone(),
+                    amount: amount.clone(),
+                    reason: reason.clone(),
+                    external_reference: external_reference.clone(),
+                }
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::FinaliseFundsOut { participant, account_id, transfer_id, action, reason } => {
+            wait_for_reservation(clients, *transfer_id).await?;
+            let request = participants::PutParticipantSettlementFunds {
+                name: participant.clone(),
+                account_id: *account_id,
+                transfer_id: *transfer_id,
+                action: action.clone(),
+                reason: reason.clone(),
+            };
+            clients.central_ledger().await?.send(request).await?;
+        }
+        Operation::CloseSettlementWindow { id, reason } => {
+            let request = settlement_windows::CloseSettlementWindow {
+                id: *id,
+                payload: settlement_windows::SettlementWindowClosurePayload {
+                    reason: reason.clone(),
+                    state: settlement_windows::SettlementWindowCloseState::Closed,
+                }
+            };
+            clients.settlement().await?.send(request).await?;
+        }
+        Operation::CreateSettlement { settlement_model, reason, settlement_windows } => {
+            let request = settlement::PostSettlement {
+                new_settlement: settlement::NewSettlement {
+                    reason: reason.clone(),
+                    settlement_model: settlement_model.clone(),
+                    settlement_windows: settlement_windows
+                        .iter()
+                        .map(|id| settlement::WindowParametersNewSettlement { id: *id })
+                        .collect(),
+                }
+            };
+            let new_settlement = clients.settlement().await?.send(request).await?.des().await?;
+            return Ok(Outcome::Settlement(new_settlement));
+        }
+        Operation::UpdateSettlementAccounts { settlement_id, state, accounts, reason, external_reference } => {
+            let mut participants: Vec<settlement::ParticipantUpdate> = Vec::new();
+            for acc in accounts {
+                let account = settlement::AccountUpdate {
+                    id: acc.account_id,
+                    state: *state,
+                    reason: reason.clone(),
+                    external_reference: Some(external_reference.clone()),
+                };
+                match participants.iter_mut().find(|p| p.id == acc.participant_id) {
+                    Some(p) => p.accounts.push(account),
+                    None => participants.push(settlement::ParticipantUpdate {
+                        id: acc.participant_id,
+                        accounts: vec![account],
+                    }),
+                }
+            }
+            let request = settlement::PutSettlement {
+                id: *settlement_id,
+                settlement_update: settlement::SettlementUpdate { participants },
+            };
+            let updated_settlement = clients.settlement().await?.send(request).await?.des().await?;
+            return Ok(Outcome::Settlement(updated_settlement));
+        }
+    }
+    Ok(Outcome::Done)
+}
+
+/// Whether an operation that failed with the given error can be skipped, because what it would
+/// create already exists
+fn skippable(operation: &Operation, err: &anyhow::Error, strict: bool) -> bool {
+    !strict && operation.creates() && crate::hints::already_exists(operation, err)
+}
+
+/// Execute the supplied operations in order, or, in dry-run mode, just return them. Operations
+/// are reported to the user as they're executed. Execution stops at the first failure, except
+/// that, unless `strict` is set, an operation that fails because what it would create already
+/// exists is skipped.
+pub async fn perform(
+    operations: Vec<Operation>,
+    clients: &mut Clients,
+    dry_run: bool,
+    strict: bool,
+    output: &Output,
+) -> anyhow::Result<Report> {
+    let mut executed = Vec::new();
+    let mut skipped = Vec::new();
+    for operation in operations {
+        let result = if dry_run {
+            output.info(format!("Would: {}", operation));
+            Outcome::Done
+        } else {
+            match execute(&operation, clients).await {
+                Err(e) if skippable(&operation, &e, strict) => {
+                    output.info(format!("Already exists, skipped: {}", operation));
+                    skipped.push(operation);
+                    continue;
+                },
+                result => {
+                    let result = result.with_context(|| Failed(operation.clone()))?;
+                    output.info(format!("Done: {}", operation));
+                    result
+                },
+            }
+        };
+        executed.push(Executed { operation, result });
+    }
+    Ok(Report { dry_run, operations: executed, skipped })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(code: &str, description: &str) -> anyhow::Error {
+        let response: fspiox_api::ErrorResponse = serde_json::from_value(serde_json::json!({
+            "errorInformation": { "errorCode": code, "errorDescription": description },
+        })).unwrap();
+        crate::MojaloopCliError::MojaloopApiError(response).into()
+    }
+
+    fn create_hub_account() -> Operation {
+        Operation::CreateHubAccount {
+            r#type: HubAccountType::HubReconciliation,
+            currency: "XOF".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn existing_resource_skipped() {
+        let err = api_error("3003", "Add Party information error - Hub account has already been registered.");
+        assert!(skippable(&create_hub_account(), &err, false));
+    }
+
+    #[test]
+    fn existing_resource_fails_when_strict() {
+        let err = api_error("3003", "Add Party information error - Hub account has already been registered.");
+        assert!(!skippable(&create_hub_account(), &err, true));
+    }
+
+    #[test]
+    fn other_errors_not_skipped() {
+        let err = api_error("3100", "Generic validation error - duplicate currency");
+        assert!(!skippable(&create_hub_account(), &err, false));
+        assert!(!skippable(&create_hub_account(), &anyhow::anyhow!("connection refused"), false));
+    }
+
+    #[test]
+    fn limits_already_set_not_skipped() {
+        let operation = Operation::SetInitialPositionAndLimits {
+            participant: FspId::from("payerfsp").unwrap(),
+            currency: "XOF".parse().unwrap(),
+            ndc: 10000,
+            position: Amount::ZERO,
+        };
+        let err = api_error("3003", "Add Party information error - Participant Limit already set");
+        assert!(!operation.creates());
+        assert!(!skippable(&operation, &err, false));
+    }
+}
@@ -0,0 +1,189 @@
+// Output handling. Every command either prints human-readable text, or, when the user supplies
+// --json, exactly one JSON document on stdout. See the "JSON output" section of the README for the
+// shape of these documents.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    pub format: Format,
+}
+
+#[derive(Serialize)]
+struct ErrorDocument<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    message: String,
+    /// The error returned by the Mojaloop API, where the failure was one
+    mojaloop_error: Option<&'a fspiox_api::ErrorResponse>,
+    /// An explanation of the error, and the command that resolves it, where we recognise it
+    hint: Option<crate::hints::Hint>,
+}
+
+impl Output {
+    pub fn new(json: bool) -> Output {
+        Output { format: if json { Format::Json } else { Format::Human } }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == Format::Json
+    }
+
+    /// Print progress information for the user. Suppressed in json mode, so that stdout contains
+    /// only the final document.
+    pub fn info(&self, msg: impl std::fmt::Display) {
+        if !self.is_json() {
+            println!("{}", msg);
+        }
+    }
+
+    /// Print a warning for the user. Printed to stderr, so that it can't be mistaken for the
+    /// result of a command that prints a document, e.g. export. Suppressed in json mode.
+    pub fn warn(&self, msg: impl std::fmt::Display) {
+        if !self.is_json() {
+            eprintln!("Warning: {}", msg);
+        }
+    }
+
+    /// Print the result of a command. In json mode the value is serialised, otherwise the
+    /// supplied function is responsible for printing it.
+    pub fn emit<T, F>(&self, value: &T, human: F) -> anyhow::Result<()>
+    where
+        T: Serialize + ?Sized,
+        F: FnOnce(&T) -> anyhow::Result<()>,
+    {
+        match self.format {
+            Format::Json => {
+                println!("{}", serde_json::to_string(value)?);
+                Ok(())
+            },
+            Format::Human => human(value),
+        }
+    }
+
+    pub fn error(&self, err: &anyhow::Error) {
+        match self.format {
+            Format::Json => {
+                let doc = ErrorDocument {
+                    error: ErrorDetail {
+                        message: format!("{:#}", err),
+                        mojaloop_error: mojaloop_error(err),
+                        hint: crate::hints::hint(err),
+                    }
+                };
+                // Serialising this structure cannot reasonably fail; if it somehow does, the
+                // plain message is better than nothing.
+                match serde_json::to_string(&doc) {
+                    Ok(s) => println!("{}", s),
+                    Err(_) => println!("{{\"error\":{{\"message\":{:?}}}}}", err.to_string()),
+                }
+            },
+            Format::Human => {
+                eprintln!("Error: {:?}", err);
+                if let Some(hint) = crate::hints::hint(err) {
+                    eprintln!();
+                    eprintln!("{}", hint);
+                }
+            },
+        }
+    }
+}
+
+/// Find a Mojaloop API error response in the chain of errors, if there is one
+pub fn mojaloop_error(err: &anyhow::Error) -> Option<&fspiox_api::ErrorResponse> {
+    err.chain().find_map(|e| {
+        if let Some(crate::MojaloopCliError::MojaloopApiError(resp)) = e.downcast_ref::<crate::MojaloopCliError>() {
+            return Some(resp);
+        }
+        if let Some(fspiox_api::clients::Error::MojaloopApiError(resp)) = e.downcast_ref::<fspiox_api::clients::Error>() {
+            return Some(resp);
+        }
+        None
+    })
+}
+
+// Documents produced by commands that don't simply return a Mojaloop API response. Where a
+// command returns data from the switch (e.g. listing participants), that data is serialised as
+// returned by the switch. Commands that modify the switch produce an operations::Report.
+
+#[derive(Serialize)]
+pub struct QuoteCreated {
+    pub quote_id: String,
+    pub transaction_id: String,
+}
+
+#[derive(Serialize)]
+pub struct TransferPrepared {
+    pub transfer_id: fspiox_api::transfer::TransferId,
+}
+
+#[derive(Serialize)]
+pub struct TransferFulfilled {
+    pub transfer_id: fspiox_api::transfer::TransferId,
+    pub state: fspiox_api::transfer::TransferState,
+}
+
+/// An asynchronous FSPIOP GET request has been sent. The switch will respond to the FSP at its
+/// callback url.
+#[derive(Serialize)]
+pub struct LookupSent {
+    pub fsp: fspiox_api::FspId,
+    pub resource: String,
+    /// None where the FSP has no endpoint configured to receive the response
+    pub callback_url: Option<String>,
+}
+
+impl std::fmt::Display for LookupSent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sent GET {} as {}. ", self.resource, self.fsp)?;
+        match &self.callback_url {
+            Some(url) => write!(f, "The switch will send the result to {}", url),
+            None => write!(f, "{} has no endpoint configured to receive the result", self.fsp),
+        }
+    }
+}
+
+/// The result of the transfer get command
+#[derive(Serialize)]
+pub struct TransferLookup {
+    #[serde(flatten)]
+    pub lookup: LookupSent,
+    /// The state of the transfer in central-ledger, where --admin was supplied
+    pub state: Option<fspiox_api::transfer::TransferState>,
+}
+
+/// The result of the settle command
+#[derive(Serialize)]
+pub struct Settled {
+    #[serde(flatten)]
+    pub report: crate::operations::Report,
+    /// The net amount of each account of the new settlement. Empty in dry-run mode.
+    pub net_positions: Vec<crate::settlements::NetPosition>,
+}
+
+#[derive(Serialize)]
+pub struct VoodooDeployment {
+    pub deployed: bool,
+}
+
+#[derive(Serialize)]
+pub struct VoodooTransfer {
+    pub transfer_id: fspiox_api::transfer::TransferId,
+    pub complete: bool,
+    pub error: Option<serde_json::Value>,
+    pub hint: Option<crate::hints::Hint>,
+}
+
+#[derive(Serialize)]
+pub struct Differences<'a> {
+    pub differences: &'a [crate::diff::Difference],
+}
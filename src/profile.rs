@@ -0,0 +1,121 @@
+// Named profiles, read from ~/.config/mojaloop-cli/config.toml (or $XDG_CONFIG_HOME). A profile
+// supplies defaults for global options, so that switching between hubs doesn't require repeating
+// them on every invocation. Options supplied on the command line take precedence. For example:
+//
+//   default_profile = "dev"
+//
+//   [profiles.dev]
+//   kubeconfig = "~/.kube/dev.yaml"
+//   context = "dev"
+//   namespace = "mojaloop"
+//   currency = "XOF"
+//
+//   [profiles.staging]
+//   central_ledger_url = "https://central-ledger.staging.example.com"
+//   settlement_url = "https://central-settlement.staging.example.com"
+//   output = "json"
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use fspiox_api::{Currency, FspId};
+
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("Couldn't read configuration file {0}: {1}")]
+    Read(String, std::io::Error),
+    #[error("Couldn't parse configuration file {0}: {1}")]
+    Parse(String, toml::de::Error),
+    #[error("Profile {0} not found in configuration file {1}. Available profiles: {2}")]
+    NotFound(String, String, String),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The profile used where none is supplied with --profile or MOJALOOP_PROFILE
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub kubeconfig: Option<PathBuf>,
+    pub context: Option<String>,
+    pub cluster: Option<String>,
+    pub user: Option<String>,
+    pub namespace: Option<String>,
+    /// The currency used by commands where none is supplied
+    #[serde(default, deserialize_with = "deserialize_currency")]
+    pub currency: Option<Currency>,
+    pub hub_name: Option<FspId>,
+    pub output: Option<OutputFormat>,
+    pub central_ledger_url: Option<url::Url>,
+    pub settlement_url: Option<url::Url>,
+    pub quote_url: Option<url::Url>,
+    pub transfer_url: Option<url::Url>,
+}
+
+/// Currencies are accepted in any case, as they are on the command line
+fn deserialize_currency<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<Currency>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|s| crate::currency::parse_currency(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// The location of the configuration file
+pub fn config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("mojaloop-cli").join("config.toml"))
+}
+
+/// Expand a leading ~ to the user's home directory
+fn expand_home(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path,
+    }
+}
+
+impl Config {
+    /// Read the configuration file. A missing file is equivalent to an empty one.
+    pub fn load() -> Result<Config, ProfileError> {
+        let path = match config_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        };
+        let path_str = path.display().to_string();
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| ProfileError::Read(path_str.clone(), e))?;
+        toml::from_str(&content).map_err(|e| ProfileError::Parse(path_str, e))
+    }
+
+    /// The named profile or, where no name is supplied, the default profile if there is one
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, ProfileError> {
+        let name = match name.or_else(|| self.default_profile.as_deref()) {
+            Some(name) => name,
+            None => return Ok(Profile::default()),
+        };
+        let mut profile = self.profiles.get(name).cloned().ok_or_else(|| ProfileError::NotFound(
+            name.to_string(),
+            config_path().map(|p| p.display().to_string()).unwrap_or_default(),
+            self.profiles.keys().cloned().collect::<Vec<_>>().join(", "),
+        ))?;
+        profile.kubeconfig = profile.kubeconfig.map(expand_home);
+        Ok(profile)
+    }
+}
@@ -0,0 +1,27 @@
+// Interactive prompts. These are only offered where a person is present, i.e. stdin and stderr are
+// both terminals, so that scripts never block waiting for input.
+
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ASKED: AtomicBool = AtomicBool::new(false);
+
+pub fn interactive() -> bool {
+    atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stderr)
+}
+
+/// Ask the user a yes/no question on stderr. Anything other than y or yes is taken as no.
+pub fn confirm(question: &str) -> std::io::Result<bool> {
+    ASKED.store(true, Ordering::Relaxed);
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Whether the user has already been asked a question during this invocation. We ask at most once,
+/// so that declining one offer isn't followed by another.
+pub fn asked() -> bool {
+    ASKED.load(Ordering::Relaxed)
+}
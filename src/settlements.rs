@@ -0,0 +1,485 @@
+// Settlement lifecycle. Once a settlement is created, each participant account in it must be moved
+// through the states PS_TRANSFERS_RECORDED, PS_TRANSFERS_RESERVED, PS_TRANSFERS_COMMITTED and
+// SETTLED, in that order. Central-settlement moves the settlement itself through the corresponding
+// states as its accounts are moved.
+
+use cli_table::{print_stdout, Cell, Table};
+use serde::Serialize;
+
+use fspiox_api::{Amount, Currency, FspId};
+use mojaloop_api::{
+    central_ledger::participants::GetParticipants,
+    clients::FspiopClient,
+    settlement::settlement::{self, SettlementState},
+    settlement::settlement_windows,
+};
+
+use crate::clients::Clients;
+use crate::operations::{Operation, SettlementAccountUpdate};
+use crate::MojaloopCliError;
+
+/// The states through which a participant account passes after the settlement is created, in
+/// order
+pub const ACCOUNT_STATES: [SettlementState; 4] = [
+    SettlementState::PsTransfersRecorded,
+    SettlementState::PsTransfersReserved,
+    SettlementState::PsTransfersCommitted,
+    SettlementState::Settled,
+];
+
+/// The position of an account state in the settlement lifecycle. None for states that cannot be
+/// advanced from, or to, i.e. ABORTED.
+fn account_state_position(state: SettlementState) -> Option<usize> {
+    match state {
+        SettlementState::PendingSettlement => Some(0),
+        s => ACCOUNT_STATES.iter().position(|a| *a == s).map(|i| i + 1),
+    }
+}
+
+/// Resolves settlement participants, which are identified by central-ledger participant ID, to
+/// participant names. The settlement service doesn't return participant names, and central-ledger
+/// doesn't return participant IDs, so we match on account IDs, which both return.
+#[derive(Debug, Clone, Default)]
+pub struct ParticipantNames {
+    accounts: Vec<(String, FspId)>,
+}
+
+impl ParticipantNames {
+    pub async fn fetch(clients: &mut Clients) -> anyhow::Result<ParticipantNames> {
+        let participants = clients.central_ledger().await?.send(GetParticipants {}).await?.des().await?;
+        Ok(ParticipantNames {
+            accounts: participants.iter()
+                .flat_map(|p| p.accounts.iter().map(move |a| (a.id.to_string(), p.name.clone())))
+                .collect(),
+        })
+    }
+
+    /// Whether central-ledger has a participant of this name
+    pub fn contains(&self, name: &FspId) -> bool {
+        self.accounts.iter().any(|(_, n)| n == name)
+    }
+
+    pub fn name(&self, participant: &settlement::SettlementParticipant) -> Option<&FspId> {
+        participant.accounts.iter().find_map(|acc| {
+            let acc_id = acc.id.to_string();
+            self.accounts.iter().find(|(id, _)| *id == acc_id).map(|(_, name)| name)
+        })
+    }
+}
+
+/// The operations required to move the accounts of a settlement to the target state. Accounts
+/// already at or beyond the target state are left alone. Where `participants` is supplied, only
+/// the accounts of those participants are moved.
+pub fn advance(
+    settlement: &settlement::Settlement,
+    target: SettlementState,
+    participants: Option<(&[FspId], &ParticipantNames)>,
+    reason: &str,
+    external_reference: &str,
+) -> Result<Vec<Operation>, MojaloopCliError> {
+    let target_position = match target {
+        SettlementState::PendingSettlement => None,
+        s => account_state_position(s),
+    }.ok_or(MojaloopCliError::InvalidSettlementAccountState(target))?;
+    if settlement.state == SettlementState::Aborted {
+        return Err(MojaloopCliError::SettlementAborted(settlement.id));
+    }
+    if let Some((names, resolver)) = participants {
+        for name in names {
+            if !settlement.participants.iter().any(|p| resolver.name(p) == Some(name)) {
+                return Err(if resolver.contains(name) {
+                    MojaloopCliError::ParticipantNotInSettlement(name.clone(), settlement.id)
+                } else {
+                    MojaloopCliError::ParticipantNotFound(name.clone())
+                });
+            }
+        }
+    }
+
+    let accounts: Vec<_> = settlement.participants.iter()
+        .filter(|p| match participants {
+            Some((names, resolver)) => resolver.name(p).map_or(false, |n| names.contains(n)),
+            None => true,
+        })
+        .flat_map(|p| p.accounts.iter().map(move |acc| (p, acc)))
+        .filter_map(|(p, acc)| account_state_position(acc.state).map(|pos| (p, acc, pos)))
+        .collect();
+
+    Ok(ACCOUNT_STATES[..target_position]
+        .iter()
+        .enumerate()
+        .filter_map(|(i, state)| {
+            // Accounts whose current state precedes this one
+            let step: Vec<_> = accounts.iter()
+                .filter(|(_, _, pos)| *pos <= i)
+                .map(|(p, acc, _)| SettlementAccountUpdate { participant_id: p.id, account_id: acc.id })
+                .collect();
+            if step.is_empty() {
+                None
+            } else {
+                Some(Operation::UpdateSettlementAccounts {
+                    settlement_id: settlement.id,
+                    state: *state,
+                    accounts: step,
+                    reason: reason.to_string(),
+                    external_reference: external_reference.to_string(),
+                })
+            }
+        })
+        .collect())
+}
+
+/// The net settlement amount of a single participant account
+#[derive(Serialize, Debug, Clone)]
+pub struct NetPosition {
+    /// None where the participant couldn't be found in central-ledger
+    pub participant: Option<FspId>,
+    pub participant_id: settlement::ParticipantId,
+    pub account_id: settlement::ParticipantCurrencyId,
+    pub currency: Currency,
+    pub amount: Amount,
+    pub state: SettlementState,
+}
+
+pub fn net_positions(settlement: &settlement::Settlement, names: &ParticipantNames) -> Vec<NetPosition> {
+    settlement.participants.iter()
+        .flat_map(|p| p.accounts.iter().map(move |acc| NetPosition {
+            participant: names.name(p).cloned(),
+            participant_id: p.id,
+            account_id: acc.id,
+            currency: acc.net_settlement_amount.currency,
+            amount: acc.net_settlement_amount.amount,
+            state: acc.state,
+        }))
+        .collect()
+}
+
+pub fn print_net_positions(positions: &[NetPosition]) -> anyhow::Result<()> {
+    let table = positions.iter()
+        .map(|p| vec![
+            p.participant.as_ref().map_or_else(|| p.participant_id.to_string(), |n| n.to_string()).cell(),
+            p.currency.cell(),
+            p.amount.cell(),
+            format!("{:?}", p.state).cell(),
+        ])
+        .table()
+        .title(vec![
+            "Participant".cell(),
+            "Currency".cell(),
+            "Net amount".cell(),
+            "State".cell(),
+        ]);
+    print_stdout(table)?;
+    Ok(())
+}
+
+/// Print the content of settlement windows, one row per currency and ledger account type. Rows
+/// are (window id, window state, currency, ledger account type, content state).
+fn print_window_content(rows: Vec<[String; 5]>) -> anyhow::Result<()> {
+    let table = rows.into_iter()
+        .map(|row| row.iter().map(|c| c.cell()).collect::<Vec<_>>())
+        .table()
+        .title(vec![
+            "Window".cell(),
+            "Window state".cell(),
+            "Currency".cell(),
+            "Ledger account type".cell(),
+            "Content state".cell(),
+        ]);
+    print_stdout(table)?;
+    Ok(())
+}
+
+/// Print a settlement for a human. Participants are shown by name where they can be found in
+/// `names`, and by ID otherwise.
+pub fn print_settlement(s: &settlement::Settlement, names: &ParticipantNames) -> anyhow::Result<()> {
+    println!("Settlement {}: {:?}", s.id, s.state);
+    println!("Created: {}. Changed: {}.", s.created_date, s.changed_date);
+    println!();
+    println!("Windows:");
+    let rows = s.settlement_windows.iter()
+        .flat_map(|w| {
+            let content: Vec<[String; 5]> = w.content.iter().flatten()
+                .map(|c| [
+                    w.id.to_string(),
+                    format!("{:?}", w.state),
+                    c.currency_id.to_string(),
+                    format!("{:?}", c.ledger_account_type),
+                    format!("{:?}", c.state),
+                ])
+                .collect();
+            if content.is_empty() {
+                vec![[w.id.to_string(), format!("{:?}", w.state), String::new(), String::new(), String::new()]]
+            } else {
+                content
+            }
+        })
+        .collect();
+    print_window_content(rows)?;
+    println!();
+    println!("Participants:");
+    print_net_positions(&net_positions(s, names))
+}
+
+/// Print a settlement window for a human
+pub fn print_settlement_window(w: &settlement_windows::SettlementWindow) -> anyhow::Result<()> {
+    println!("Settlement window {}: {:?}", w.settlement_window_id, w.state);
+    println!(
+        "Created: {}. Changed: {}.",
+        w.created_date,
+        w.changed_date.map_or_else(|| "never".to_string(), |d| d.to_string()),
+    );
+    if let Some(reason) = &w.reason {
+        println!("Reason: {}", reason);
+    }
+    let rows: Vec<[String; 5]> = w.content.iter().flatten()
+        .map(|c| [
+            w.settlement_window_id.to_string(),
+            format!("{:?}", w.state),
+            c.currency_id.to_string(),
+            format!("{:?}", c.ledger_account_type),
+            format!("{:?}", c.state),
+        ])
+        .collect();
+    if rows.is_empty() {
+        println!("No content");
+        return Ok(());
+    }
+    println!();
+    print_window_content(rows)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ReportFormat, String> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(ReportFormat::Table),
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("Unknown report format {}. Expected one of table, csv, json", s)),
+        }
+    }
+}
+
+/// The net position of each participant account in a settlement
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub settlement_id: settlement::SettlementId,
+    pub state: SettlementState,
+    pub net_positions: Vec<NetPosition>,
+}
+
+impl Report {
+    pub fn new(settlement: &settlement::Settlement, names: &ParticipantNames) -> Report {
+        let mut net_positions = net_positions(settlement, names);
+        net_positions.sort_by_key(|p| (
+            p.participant.as_ref().map(|n| n.to_string()),
+            p.currency.to_string(),
+        ));
+        Report {
+            settlement_id: settlement.id,
+            state: settlement.state,
+            net_positions,
+        }
+    }
+
+    pub fn csv(&self) -> String {
+        let mut csv = String::from("settlement_id,participant,participant_id,account_id,currency,net_amount,state\n");
+        for p in &self.net_positions {
+            let fields = [
+                self.settlement_id.to_string(),
+                p.participant.as_ref().map_or_else(String::new, |n| n.to_string()),
+                p.participant_id.to_string(),
+                p.account_id.to_string(),
+                p.currency.to_string(),
+                p.amount.to_string(),
+                format!("{:?}", p.state),
+            ];
+            csv.push_str(&fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    pub fn render(&self, format: ReportFormat) -> anyhow::Result<String> {
+        Ok(match format {
+            ReportFormat::Csv => self.csv(),
+            ReportFormat::Json => serde_json::to_string_pretty(self)? + "\n",
+            ReportFormat::Table => {
+                let table = self.net_positions.iter()
+                    .map(|p| vec![
+                        p.participant.as_ref().map_or_else(|| p.participant_id.to_string(), |n| n.to_string()).cell(),
+                        p.currency.cell(),
+                        p.amount.cell(),
+                        p.account_id.cell(),
+                    ])
+                    .table()
+                    .title(vec![
+                        "Participant".cell(),
+                        "Currency".cell(),
+                        "Net amount".cell(),
+                        "Account ID".cell(),
+                    ]);
+                format!(
+                    "Settlement {} ({:?})\n{}\n",
+                    self.settlement_id,
+                    self.state,
+                    table.display()?,
+                )
+            }
+        })
+    }
+}
+
+/// Quote a CSV field where necessary, per RFC 4180
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORDED: SettlementState = SettlementState::PsTransfersRecorded;
+    const RESERVED: SettlementState = SettlementState::PsTransfersReserved;
+    const COMMITTED: SettlementState = SettlementState::PsTransfersCommitted;
+    const SETTLED: SettlementState = SettlementState::Settled;
+
+    /// A settlement with one account for each supplied (participant id, account id, account state)
+    fn settlement(state: &str, accounts: &[(u32, u32, &str)]) -> settlement::Settlement {
+        let date = "2021-09-01T00:00:00.000Z";
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "state": state,
+            "createdDate": date,
+            "changedDate": date,
+            "settlementWindows": [{
+                "id": 1,
+                "state": "PENDING_SETTLEMENT",
+                "reason": "test",
+                "createdDate": date,
+                "changedDate": date,
+                "content": [],
+            }],
+            "participants": accounts.iter().map(|(participant_id, account_id, state)| serde_json::json!({
+                "id": participant_id,
+                "accounts": [{
+                    "id": account_id,
+                    "state": state,
+                    "reason": "test",
+                    "netSettlementAmount": { "amount": "10", "currency": "XOF" },
+                }],
+            })).collect::<Vec<_>>(),
+        })).unwrap()
+    }
+
+    fn names() -> ParticipantNames {
+        ParticipantNames {
+            accounts: vec![
+                ("1".to_string(), FspId::from("payerfsp").unwrap()),
+                ("2".to_string(), FspId::from("payeefsp").unwrap()),
+                ("3".to_string(), FspId::from("otherfsp").unwrap()),
+            ],
+        }
+    }
+
+    /// The state and account ids of each operation
+    fn steps(operations: Vec<Operation>) -> Vec<(SettlementState, Vec<String>)> {
+        operations.into_iter()
+            .map(|op| match op {
+                Operation::UpdateSettlementAccounts { state, accounts, .. } =>
+                    (state, accounts.iter().map(|a| a.account_id.to_string()).collect()),
+                op => panic!("Unexpected operation {}", op),
+            })
+            .collect()
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn each_transition_in_order() {
+        let s = settlement("PENDING_SETTLEMENT", &[(1, 1, "PENDING_SETTLEMENT"), (2, 2, "PENDING_SETTLEMENT")]);
+        let cases = [
+            (RECORDED, vec![(RECORDED, ids(&["1", "2"]))]),
+            (RESERVED, vec![(RECORDED, ids(&["1", "2"])), (RESERVED, ids(&["1", "2"]))]),
+            (COMMITTED, vec![(RECORDED, ids(&["1", "2"])), (RESERVED, ids(&["1", "2"])), (COMMITTED, ids(&["1", "2"]))]),
+            (SETTLED, vec![
+                (RECORDED, ids(&["1", "2"])),
+                (RESERVED, ids(&["1", "2"])),
+                (COMMITTED, ids(&["1", "2"])),
+                (SETTLED, ids(&["1", "2"])),
+            ]),
+        ];
+        for (target, expected) in cases.iter() {
+            assert_eq!(&steps(advance(&s, *target, None, "", "").unwrap()), expected, "{:?}", target);
+        }
+    }
+
+    #[test]
+    fn accounts_advance_from_their_current_state() {
+        let s = settlement("PS_TRANSFERS_RECORDED", &[(1, 1, "PS_TRANSFERS_RESERVED"), (2, 2, "PS_TRANSFERS_RECORDED")]);
+        assert_eq!(
+            steps(advance(&s, COMMITTED, None, "", "").unwrap()),
+            vec![(RESERVED, ids(&["2"])), (COMMITTED, ids(&["1", "2"]))],
+        );
+        assert!(advance(&s, RECORDED, None, "", "").unwrap().is_empty());
+    }
+
+    #[test]
+    fn settled_and_aborted_accounts_left_alone() {
+        let s = settlement("PS_TRANSFERS_COMMITTED", &[(1, 1, "SETTLED"), (2, 2, "ABORTED")]);
+        assert!(advance(&s, SETTLED, None, "", "").unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_named_participants_advance() {
+        let s = settlement("PENDING_SETTLEMENT", &[(1, 1, "PENDING_SETTLEMENT"), (2, 2, "PENDING_SETTLEMENT")]);
+        let payer = [FspId::from("payerfsp").unwrap()];
+        assert_eq!(
+            steps(advance(&s, RECORDED, Some((&payer, &names())), "", "").unwrap()),
+            vec![(RECORDED, ids(&["1"]))],
+        );
+    }
+
+    #[test]
+    fn unknown_participant() {
+        let s = settlement("PENDING_SETTLEMENT", &[(1, 1, "PENDING_SETTLEMENT")]);
+        let unknown = [FspId::from("payerfsp").unwrap(), FspId::from("nobody").unwrap()];
+        assert!(matches!(
+            advance(&s, RECORDED, Some((&unknown, &names())), "", ""),
+            Err(MojaloopCliError::ParticipantNotFound(name)) if name.to_string() == "nobody",
+        ));
+        let absent = [FspId::from("otherfsp").unwrap()];
+        assert!(matches!(
+            advance(&s, RECORDED, Some((&absent, &names())), "", ""),
+            Err(MojaloopCliError::ParticipantNotInSettlement(..)),
+        ));
+    }
+
+    #[test]
+    fn invalid_target_or_settlement() {
+        let s = settlement("PENDING_SETTLEMENT", &[(1, 1, "PENDING_SETTLEMENT")]);
+        for target in &[SettlementState::PendingSettlement, SettlementState::Aborted] {
+            assert!(matches!(
+                advance(&s, *target, None, "", ""),
+                Err(MojaloopCliError::InvalidSettlementAccountState(_)),
+            ));
+        }
+        let aborted = settlement("ABORTED", &[(1, 1, "PENDING_SETTLEMENT")]);
+        assert!(matches!(advance(&aborted, RECORDED, None, "", ""), Err(MojaloopCliError::SettlementAborted(_))));
+    }
+}
@@ -0,0 +1,220 @@
+// The switch configuration document. This describes the desired state of a switch: hub accounts,
+// settlement models, and participants with their accounts, limits and endpoints. It's consumed by
+// the apply subcommand. It can be written as either YAML or JSON, for example:
+//
+//   hub:
+//     currencies: [ SEK, XOF ]
+//   settlement_models:
+//     - name: DEFERREDNET
+//       currency: SEK
+//   participants:
+//     - name: payerfsp
+//       endpoint: http://payerfsp.sim/fspiopapi
+//       accounts:
+//         - currency: SEK
+//           ndc: 10000
+//           position: 0
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use fspiox_api::{Amount, Currency, FspId};
+use mojaloop_api::central_ledger::settlement_models;
+
+#[derive(Error, Debug)]
+pub enum SwitchConfigError {
+    #[error("Couldn't read switch configuration file {0}: {1}")]
+    Read(String, std::io::Error),
+    #[error("Couldn't parse switch configuration file {0} as JSON: {1}")]
+    ParseJson(String, serde_json::Error),
+    #[error("Couldn't parse switch configuration file {0} as YAML: {1}")]
+    ParseYaml(String, serde_yaml::Error),
+    #[error("Participant {0} appears more than once in the switch configuration")]
+    DuplicateParticipant(FspId),
+    #[error("Participant {0} has more than one {1} account in the switch configuration")]
+    DuplicateParticipantAccount(FspId, Currency),
+    #[error("Participant {0} must have at least one account")]
+    ParticipantWithoutAccounts(FspId),
+    #[error("Invalid settlement model name {0}: {1}")]
+    InvalidSettlementModelName(String, String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SwitchConfig {
+    #[serde(default)]
+    pub hub: HubConfig,
+    #[serde(default)]
+    pub settlement_models: Vec<SettlementModelConfig>,
+    #[serde(default)]
+    pub participants: Vec<ParticipantConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HubConfig {
+    /// Currencies in which the hub should have both reconciliation and multilateral settlement
+    /// accounts
+    #[serde(default)]
+    pub currencies: Vec<Currency>,
+}
+
+/// Fields and defaults correspond to those of the `hub settlement-model create` subcommand.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SettlementModelConfig {
+    pub name: settlement_models::SettlementModelName,
+    pub currency: Currency,
+    #[serde(default = "default_true")]
+    pub auto_position_reset: bool,
+    #[serde(default = "default_ledger_account_type")]
+    pub ledger_account_type: settlement_models::LedgerAccountType,
+    #[serde(default = "default_settlement_account_type")]
+    pub settlement_account_type: settlement_models::SettlementAccountType,
+    #[serde(default = "default_true")]
+    pub require_liquidity_check: bool,
+    #[serde(default = "default_settlement_delay")]
+    pub settlement_delay: settlement_models::SettlementDelay,
+    #[serde(default = "default_settlement_granularity")]
+    pub settlement_granularity: settlement_models::SettlementGranularity,
+    #[serde(default = "default_settlement_interchange")]
+    pub settlement_interchange: settlement_models::SettlementInterchange,
+}
+
+impl From<&SettlementModelConfig> for settlement_models::SettlementModel {
+    fn from(m: &SettlementModelConfig) -> settlement_models::SettlementModel {
+        settlement_models::SettlementModel {
+            auto_position_reset: m.auto_position_reset,
+            ledger_account_type: m.ledger_account_type,
+            settlement_account_type: m.settlement_account_type,
+            name: m.name.clone(),
+            require_liquidity_check: m.require_liquidity_check,
+            settlement_delay: m.settlement_delay,
+            settlement_granularity: m.settlement_granularity,
+            settlement_interchange: m.settlement_interchange,
+            currency: m.currency,
+        }
+    }
+}
+
+impl From<&settlement_models::SettlementModel> for SettlementModelConfig {
+    fn from(m: &settlement_models::SettlementModel) -> SettlementModelConfig {
+        SettlementModelConfig {
+            name: m.name.clone(),
+            currency: m.currency,
+            auto_position_reset: m.auto_position_reset,
+            ledger_account_type: m.ledger_account_type,
+            settlement_account_type: m.settlement_account_type,
+            require_liquidity_check: m.require_liquidity_check,
+            settlement_delay: m.settlement_delay,
+            settlement_granularity: m.settlement_granularity,
+            settlement_interchange: m.settlement_interchange,
+        }
+    }
+}
+
+impl SettlementModelConfig {
+    /// A deferred net settlement model for the given currency, with the defaults of
+    /// `hub settlement-model create`. Settlement model names are unique, so the model is named for
+    /// its currency, e.g. DEFERREDNETXOF.
+    pub fn deferred_net(currency: Currency) -> Result<SettlementModelConfig, SwitchConfigError> {
+        let name = format!("DEFERREDNET{}", currency);
+        let name = name.parse::<settlement_models::SettlementModelName>()
+            .map_err(|e| SwitchConfigError::InvalidSettlementModelName(name.clone(), e.to_string()))?;
+        Ok(SettlementModelConfig {
+            name,
+            currency,
+            auto_position_reset: default_true(),
+            ledger_account_type: default_ledger_account_type(),
+            settlement_account_type: default_settlement_account_type(),
+            require_liquidity_check: default_true(),
+            settlement_delay: default_settlement_delay(),
+            settlement_granularity: default_settlement_granularity(),
+            settlement_interchange: default_settlement_interchange(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ParticipantConfig {
+    pub name: FspId,
+    /// The host to which all FSPIOP requests destined for this participant will be delivered. If
+    /// not supplied, endpoints will not be modified.
+    pub endpoint: Option<url::Url>,
+    pub accounts: Vec<ParticipantAccountConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ParticipantAccountConfig {
+    pub currency: Currency,
+    /// The net debit cap for this account
+    #[serde(default)]
+    pub ndc: u32,
+    /// The initial position of this account. Only used when the account is created.
+    #[serde(default = "default_amount")]
+    pub position: Amount,
+}
+
+fn default_true() -> bool { true }
+fn default_amount() -> Amount { Amount::ZERO }
+fn default_ledger_account_type() -> settlement_models::LedgerAccountType {
+    settlement_models::LedgerAccountType::Position
+}
+fn default_settlement_account_type() -> settlement_models::SettlementAccountType {
+    settlement_models::SettlementAccountType::Settlement
+}
+fn default_settlement_delay() -> settlement_models::SettlementDelay {
+    settlement_models::SettlementDelay::Deferred
+}
+fn default_settlement_granularity() -> settlement_models::SettlementGranularity {
+    settlement_models::SettlementGranularity::Net
+}
+fn default_settlement_interchange() -> settlement_models::SettlementInterchange {
+    settlement_models::SettlementInterchange::Multilateral
+}
+
+impl SwitchConfig {
+    /// Read a switch configuration from a file. Files with a .json extension are parsed as JSON,
+    /// anything else as YAML.
+    pub fn from_file(path: &std::path::Path) -> Result<SwitchConfig, SwitchConfigError> {
+        let path_str = path.display().to_string();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| SwitchConfigError::Read(path_str.clone(), e))?;
+        let config: SwitchConfig = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&content)
+                .map_err(|e| SwitchConfigError::ParseJson(path_str, e))?,
+            _ => serde_yaml::from_str(&content)
+                .map_err(|e| SwitchConfigError::ParseYaml(path_str, e))?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Serialise to JSON or YAML
+    pub fn serialise(&self, json: bool) -> anyhow::Result<String> {
+        Ok(if json {
+            serde_json::to_string_pretty(self)?
+        } else {
+            serde_yaml::to_string(self)?
+        })
+    }
+
+    fn validate(&self) -> Result<(), SwitchConfigError> {
+        for (i, p) in self.participants.iter().enumerate() {
+            if self.participants[..i].iter().any(|other| other.name == p.name) {
+                return Err(SwitchConfigError::DuplicateParticipant(p.name.clone()));
+            }
+            if p.accounts.is_empty() {
+                return Err(SwitchConfigError::ParticipantWithoutAccounts(p.name.clone()));
+            }
+            for (j, acc) in p.accounts.iter().enumerate() {
+                if p.accounts[..j].iter().any(|other| other.currency == acc.currency) {
+                    return Err(SwitchConfigError::DuplicateParticipantAccount(p.name.clone(), acc.currency));
+                }
+            }
+        }
+        Ok(())
+    }
+}